@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE
+    work_data
+ADD
+    COLUMN model TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,22 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS work_data_external_id_idx;
+
+DROP INDEX IF EXISTS work_data_status_idx;
+
+ALTER TABLE
+    work_data DROP COLUMN clean_time;
+
+ALTER TABLE
+    work_data DROP COLUMN finish_time;
+
+ALTER TABLE
+    work_data DROP COLUMN fetch_time;
+
+ALTER TABLE
+    work_data DROP COLUMN upload_start_time;
+
+ALTER TABLE
+    work_data DROP COLUMN progress;
+
+ALTER TABLE
+    work_data DROP COLUMN status;
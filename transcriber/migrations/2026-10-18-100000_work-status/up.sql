@@ -0,0 +1,52 @@
+-- Your SQL goes here
+ALTER TABLE
+    work_data
+ADD
+    COLUMN status TEXT NOT NULL DEFAULT 'queued';
+
+ALTER TABLE
+    work_data
+ADD
+    COLUMN progress INT NOT NULL DEFAULT 0;
+
+ALTER TABLE
+    work_data
+ADD
+    COLUMN upload_start_time TIMESTAMP NULL;
+
+ALTER TABLE
+    work_data
+ADD
+    COLUMN fetch_time TIMESTAMP NULL;
+
+ALTER TABLE
+    work_data
+ADD
+    COLUMN finish_time TIMESTAMP NULL;
+
+ALTER TABLE
+    work_data
+ADD
+    COLUMN clean_time TIMESTAMP NULL;
+
+CREATE INDEX work_data_status_idx ON work_data(status);
+
+CREATE INDEX work_data_external_id_idx ON work_data(external_id);
+
+-- existing jobs get the status derived from the data kept before the status column,
+-- the finish of an uploaded job is not recorded, so it stays transcribing
+UPDATE
+    work_data
+SET
+    status = 'failed',
+    finish_time = updated
+WHERE
+    error_msg <> '';
+
+UPDATE
+    work_data
+SET
+    status = 'transcribing'
+WHERE
+    error_msg = ''
+    AND upload_time IS NOT NULL;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS work_data_created_idx;
+
+ALTER TABLE
+    work_data DROP COLUMN office;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE
+    work_data
+ADD
+    COLUMN office TEXT NOT NULL DEFAULT '';
+
+CREATE INDEX work_data_created_idx ON work_data(created);
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE
+    work_data
+ADD
+    COLUMN result_file TEXT NOT NULL DEFAULT '';
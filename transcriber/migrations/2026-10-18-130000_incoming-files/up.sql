@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE incoming_files(
+    base_dir TEXT NOT NULL,
+    name TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    modified TIMESTAMP NOT NULL,
+    seen TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (base_dir, name)
+);
@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE
+    work_data DROP COLUMN duration;
+
+ALTER TABLE
+    work_data DROP COLUMN sample_rate;
+
+ALTER TABLE
+    work_data DROP COLUMN channels;
+
+ALTER TABLE
+    work_data DROP COLUMN codec;
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+ALTER TABLE
+    work_data
+ADD
+    COLUMN duration DOUBLE PRECISION NULL;
+
+ALTER TABLE
+    work_data
+ADD
+    COLUMN sample_rate INT NULL;
+
+ALTER TABLE
+    work_data
+ADD
+    COLUMN channels INT NULL;
+
+ALTER TABLE
+    work_data
+ADD
+    COLUMN codec TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS work_data_parent_id_idx;
+
+ALTER TABLE
+    work_data DROP COLUMN parent_id;
+
+ALTER TABLE
+    work_data DROP COLUMN chunks;
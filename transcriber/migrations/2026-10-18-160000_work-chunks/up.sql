@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TABLE
+    work_data
+ADD
+    COLUMN parent_id TEXT NOT NULL DEFAULT '';
+
+ALTER TABLE
+    work_data
+ADD
+    COLUMN chunks INT NOT NULL DEFAULT 0;
+
+CREATE INDEX work_data_parent_id_idx ON work_data(parent_id);
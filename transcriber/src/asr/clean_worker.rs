@@ -1,27 +1,33 @@
+use std::sync::Arc;
+
 use pgmq::Message;
 use tokio_util::sync::CancellationToken;
 
-use super::client::ASRClient;
+use super::AsrBackend;
 use crate::data::api::CleanMessage;
 use crate::postgres::queue::PQueue;
+use crate::StatusTracker;
 
 pub struct Worker {
     queue: PQueue,
     ct: CancellationToken,
-    asr_client: ASRClient,
+    asr_client: Arc<dyn AsrBackend + Send + Sync>,
+    tracker: Box<dyn StatusTracker + Send + Sync>,
 }
 
 impl Worker {
     pub async fn new(
         ct: CancellationToken,
-        asr_client: ASRClient,
+        asr_client: Arc<dyn AsrBackend + Send + Sync>,
         queue: PQueue,
+        tracker: Box<dyn StatusTracker + Send + Sync>,
     ) -> anyhow::Result<Self> {
         log::info!("Init Result Worker");
         Ok(Self {
             queue,
             ct,
             asr_client,
+            tracker,
         })
     }
 
@@ -29,24 +35,30 @@ impl Worker {
         crate::postgres::queue::run(
             self.queue.clone(),
             |msg: Message<CleanMessage>| async move { self.process_msg(msg).await },
+            |msg: Message<CleanMessage>, err| async move { self.exhausted(msg, &err).await },
             self.ct.clone(),
             "clean worker",
         )
         .await
     }
 
+    /// Nothing to finish, the job data stays in the ASR until its own cleanup
+    pub async fn exhausted(&self, msg: Message<CleanMessage>, error: &str) -> anyhow::Result<()> {
+        log::error!("Max retries reached {:?}: {}", msg.message, error);
+        Ok(())
+    }
+
     pub async fn process_msg(&self, msg: Message<CleanMessage>) -> anyhow::Result<bool> {
         log::info!("Process {:?}", msg);
         let msg_asr = msg.message;
-        if msg.read_ct > 3 {
-            log::error!("Max retries reached {:?}", msg_asr);
-            return Ok(true);
-        }
         if msg_asr.external_id.is_empty() {
             log::debug!("Empty external_id");
             return Ok(true);
         }
         self.clean(&msg_asr.external_id).await?;
+        if let Err(err) = self.tracker.set_cleaned(&msg_asr.external_id).await {
+            log::error!("can't save clean time: {}", err);
+        }
         log::info!("done: {}", msg.msg_id);
         Ok(true)
     }
@@ -56,3 +68,50 @@ impl Worker {
         self.asr_client.clean(id).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asr::fake::FakeBackend;
+    use crate::postgres::work::MemTracker;
+
+    async fn make_worker(fake: &FakeBackend) -> Worker {
+        Worker::new(
+            CancellationToken::new(),
+            Arc::new(fake.clone()),
+            PQueue::new_test("clean").await,
+            Box::new(MemTracker::default()),
+        )
+        .await
+        .unwrap()
+    }
+
+    fn make_msg(external_id: &str, read_ct: i32) -> Message<CleanMessage> {
+        Message {
+            msg_id: 1,
+            vt: chrono::Utc::now(),
+            enqueued_at: chrono::Utc::now(),
+            read_ct,
+            message: CleanMessage {
+                external_id: external_id.to_string(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_clean() {
+        let fake = FakeBackend::new();
+        let worker = make_worker(&fake).await;
+        let res = worker.process_msg(make_msg("ext-1", 1)).await.unwrap();
+        assert!(res);
+        assert_eq!(vec!["ext-1".to_string()], fake.cleaned());
+    }
+
+    #[tokio::test]
+    async fn test_skip_empty() {
+        let fake = FakeBackend::new();
+        let worker = make_worker(&fake).await;
+        assert!(worker.process_msg(make_msg("", 1)).await.unwrap());
+        assert!(fake.cleaned().is_empty());
+    }
+}
@@ -1,6 +1,7 @@
-use std::{error::Error, fs, path::Path, time::Duration};
+use std::{collections::HashMap, error::Error, fs, path::Path, time::Duration};
 
 use again::RetryPolicy;
+use async_trait::async_trait;
 use reqwest::{
     header::{HeaderMap, HeaderName, HeaderValue},
     multipart, Body,
@@ -9,12 +10,18 @@ use serde::Deserialize;
 use tokio::fs::File;
 use tokio_util::codec::{BytesCodec, FramedRead};
 
+use super::{AsrBackend, UploadParams};
+use crate::data::meta;
+
+/// Upload fields set by the client, the job params can't override them
+const UPLOAD_FIELDS: [&str; 3] = ["recognizer", "numberOfSpeakers", "file"];
+
 #[derive(Deserialize, Debug)]
 struct UploadResponse {
     id: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, Default)]
 pub struct StatusResponse {
     pub id: String,
     #[serde(default, rename = "errorCode")]
@@ -36,6 +43,8 @@ pub struct ASRClient {
     model: String,
     client: reqwest::Client,
     old_clean: bool,
+    /// Upload field names of the job params, the param name is sent if not mapped
+    param_names: HashMap<String, String>,
 }
 
 impl ASRClient {
@@ -57,11 +66,70 @@ impl ASRClient {
             client,
             model: model.to_string(),
             old_clean,
+            param_names: HashMap::new(),
         })
     }
 
-    pub async fn upload(&self, file_path: &str) -> anyhow::Result<String> {
+    /// Renames the job params for the upload, e.g. `skip_punctuation` to `skipPunctuation`
+    pub fn with_param_names(mut self, names: HashMap<String, String>) -> Self {
+        self.param_names = names;
+        self
+    }
+
+    /// The multipart text fields of the upload
+    fn form_fields(
+        &self,
+        model: &str,
+        speakers: &str,
+        params: &UploadParams,
+    ) -> Vec<(String, String)> {
+        let mut res = vec![
+            ("recognizer".to_string(), model.to_string()),
+            ("numberOfSpeakers".to_string(), speakers.to_string()),
+        ];
+        for (k, v) in params.params.iter() {
+            let name = self.param_names.get(k).unwrap_or(k);
+            if UPLOAD_FIELDS.contains(&name.as_str()) || res.iter().any(|(n, _)| n == name) {
+                log::warn!("skip param '{}', field '{}' is already set", k, name);
+                continue;
+            }
+            res.push((name.clone(), v.clone()));
+        }
+        res
+    }
+}
+
+/// Parses the `param=fieldName` pairs separated by commas
+pub fn parse_param_names(value: &str) -> anyhow::Result<HashMap<String, String>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(|pair| {
+            let (k, v) = pair
+                .split_once('=')
+                .map(|(k, v)| (k.trim(), v.trim()))
+                .filter(|(k, v)| meta::is_param_name(k) && meta::is_param_name(v))
+                .ok_or_else(|| anyhow::anyhow!("wrong param name mapping '{}'", pair))?;
+            Ok((k.to_lowercase(), v.to_string()))
+        })
+        .collect()
+}
+
+#[async_trait]
+impl AsrBackend for ASRClient {
+    async fn upload(&self, file_path: &str, params: &UploadParams) -> anyhow::Result<String> {
         log::info!("Send file to ASR: {}", file_path);
+        let speakers = params.speakers.map(|v| v.to_string()).unwrap_or_default();
+        log::info!("speakers: '{}'", speakers);
+        let model = params
+            .model
+            .clone()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| self.model.clone());
+        log::info!("model: {}", model);
+        let fields = self.form_fields(&model, &speakers, params);
+        log::info!("fields: {:?}", fields);
         let metadata = fs::metadata(file_path)?;
         let file_size = metadata.len();
         let timeout = get_timeout(file_size);
@@ -107,9 +175,11 @@ impl ASRClient {
                         .mime_str("audio/bin")
                         .map_err(|err| format!("can't prepare multipart: {}", err))?;
 
-                    let form = multipart::Form::new()
-                        .text("recognizer", self.model.clone())
-                        .text("numberOfSpeakers", "")
+                    let form = fields
+                        .iter()
+                        .fold(multipart::Form::new(), |form, (k, v)| {
+                            form.text(k.clone(), v.clone())
+                        })
                         .part("file", some_file);
 
                     log::info!("call: {}", url);
@@ -140,7 +210,7 @@ impl ASRClient {
         Ok(parsed_json.id)
     }
 
-    pub async fn status(&self, id: &str) -> Result<StatusResponse, Box<dyn Error + Send + Sync>> {
+    async fn status(&self, id: &str) -> anyhow::Result<StatusResponse> {
         log::info!("check status: {}", id);
         let mut headers = HeaderMap::new();
         headers.try_insert(
@@ -169,22 +239,27 @@ impl ASRClient {
                 },
                 is_retry_err,
             )
-            .await?;
+            .await
+            .map_err(anyhow::Error::msg)?;
         if res.status().is_success() {
             log::info!("call ok");
             let content = res.text().await?;
             let parsed_json: StatusResponse = serde_json::from_str(&content)
-                .map_err(|err| format!("can't deserialize '{}': {}", content, err))?;
-            Ok::<StatusResponse, Box<dyn Error + Send + Sync>>(parsed_json)
+                .map_err(|err| anyhow::anyhow!("can't deserialize '{}': {}", content, err))?;
+            Ok(parsed_json)
         } else {
             log::info!("call failed");
             let status = res.status();
             let body = res.text().await.unwrap_or("can't read body".into());
-            Err(format!("Request failed with status: {}\n{}", status, body).into())
+            Err(anyhow::anyhow!(
+                "Request failed with status: {}\n{}",
+                status,
+                body
+            ))
         }
     }
 
-    pub async fn result(&self, id: &str, file_name: &str) -> anyhow::Result<String> {
+    async fn result(&self, id: &str, file_name: &str) -> anyhow::Result<String> {
         log::info!("load file: {}, {}", id, file_name);
         let mut headers = HeaderMap::new();
         headers.try_insert(
@@ -231,7 +306,7 @@ impl ASRClient {
         }
     }
 
-    pub async fn clean(&self, id: &str) -> anyhow::Result<()> {
+    async fn clean(&self, id: &str) -> anyhow::Result<()> {
         log::info!("clean id: {}", id);
         let url = get_clean_url(&self.url, self.old_clean, id);
         log::info!("clean url: {}", url);
@@ -331,6 +406,67 @@ mod tests {
         assert_eq!(wanted, actual)
     }
 
+    #[test]
+    fn test_form_fields() {
+        let client = ASRClient::new("http://olia", "", "ben", false)
+            .unwrap()
+            .with_param_names(parse_param_names("skip_punctuation=skipPunctuation").unwrap());
+        let params = UploadParams {
+            params: [
+                ("skip_punctuation", "true"),
+                ("numberofspeakers", "3"),
+                ("x", "1"),
+            ]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+            ..Default::default()
+        };
+        let actual = client.form_fields("ben", "2", &params);
+        let wanted: Vec<(String, String)> = [
+            ("recognizer", "ben"),
+            ("numberOfSpeakers", "2"),
+            ("numberofspeakers", "3"),
+            ("skipPunctuation", "true"),
+            ("x", "1"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        assert_eq!(wanted, actual);
+    }
+
+    #[test]
+    fn test_form_fields_no_override() {
+        let client = ASRClient::new("http://olia", "", "ben", false)
+            .unwrap()
+            .with_param_names(
+                parse_param_names("model=recognizer, speakers=numberOfSpeakers").unwrap(),
+            );
+        let params = UploadParams {
+            params: [("model", "other"), ("speakers", "5")]
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(2, client.form_fields("ben", "", &params).len());
+    }
+
+    #[test_case("", 0; "empty")]
+    #[test_case("a=b", 1; "one")]
+    #[test_case(" a = bB , c=d ", 2; "spaces")]
+    fn test_parse_param_names(value: &str, wanted: usize) {
+        assert_eq!(wanted, parse_param_names(value).unwrap().len());
+    }
+
+    #[test_case("a"; "no value")]
+    #[test_case("a=b c"; "space")]
+    #[test_case("a=b&c"; "symbol")]
+    fn test_parse_param_names_fail(value: &str) {
+        assert!(parse_param_names(value).is_err());
+    }
+
     #[test_case("http://olia/", false, "10", "http://olia//clean.service/delete/10")]
     #[test_case("http://olia/", true, "10", "http://olia//clean.service/10")]
     fn test_clean_url(url: &str, old: bool, id: &str, wanted: &str) {
@@ -0,0 +1,104 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+
+use super::{client::StatusResponse, AsrBackend, UploadParams};
+
+#[derive(Default)]
+struct State {
+    next_id: u32,
+    uploaded: Vec<(String, UploadParams)>,
+    cleaned: Vec<String>,
+    results: HashMap<String, String>,
+    status: StatusResponse,
+    fail_upload: Option<String>,
+}
+
+/// In-process ASR engine: remembers the calls and answers with the configured data
+#[derive(Clone, Default)]
+pub struct FakeBackend {
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeBackend {
+    pub fn new() -> Self {
+        let res = Self::default();
+        res.set_status("COMPLETED");
+        res
+    }
+
+    pub fn set_status(&self, status: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.status.status = Some(status.to_string());
+        state.status.error_code = None;
+        state.status.error = None;
+    }
+
+    pub fn set_error(&self, code: &str, error: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.status.status = Some("ERROR".to_string());
+        state.status.error_code = Some(code.to_string());
+        state.status.error = Some(error.to_string());
+    }
+
+    pub fn set_progress(&self, progress: u32) {
+        self.state.lock().unwrap().status.progress = Some(progress);
+    }
+
+    pub fn set_result(&self, file_name: &str, txt: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .results
+            .insert(file_name.to_string(), txt.to_string());
+    }
+
+    pub fn fail_upload(&self, error: &str) {
+        self.state.lock().unwrap().fail_upload = Some(error.to_string());
+    }
+
+    pub fn uploaded(&self) -> Vec<(String, UploadParams)> {
+        self.state.lock().unwrap().uploaded.clone()
+    }
+
+    pub fn cleaned(&self) -> Vec<String> {
+        self.state.lock().unwrap().cleaned.clone()
+    }
+}
+
+#[async_trait]
+impl AsrBackend for FakeBackend {
+    async fn upload(&self, file_path: &str, params: &UploadParams) -> anyhow::Result<String> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(err) = &state.fail_upload {
+            return Err(anyhow::anyhow!("{}", err));
+        }
+        state.next_id += 1;
+        state.uploaded.push((file_path.to_string(), params.clone()));
+        Ok(format!("fake-{}", state.next_id))
+    }
+
+    async fn status(&self, id: &str) -> anyhow::Result<StatusResponse> {
+        let state = self.state.lock().unwrap();
+        let mut res = state.status.clone();
+        res.id = id.to_string();
+        Ok(res)
+    }
+
+    async fn result(&self, id: &str, file_name: &str) -> anyhow::Result<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .results
+            .get(file_name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("no result {} for {}", file_name, id))
+    }
+
+    async fn clean(&self, id: &str) -> anyhow::Result<()> {
+        self.state.lock().unwrap().cleaned.push(id.to_string());
+        Ok(())
+    }
+}
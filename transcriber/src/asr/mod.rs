@@ -1,4 +1,35 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+
+use self::client::StatusResponse;
+
 pub mod clean_worker;
 pub mod client;
+pub mod fake;
 pub mod res_worker;
+pub mod restore;
+pub mod status_worker;
 pub mod worker;
+
+/// Recognizer settings sent together with the audio
+#[derive(Debug, Clone, Default)]
+pub struct UploadParams {
+    pub speakers: Option<u32>,
+    /// Other recognizer fields, see `meta::asr_params`
+    pub params: BTreeMap<String, String>,
+    /// Recognizer to use, the backend's default if `None`
+    pub model: Option<String>,
+}
+
+/// Speech recognition engine used by the queue workers
+#[async_trait]
+pub trait AsrBackend {
+    /// Sends the audio file for transcription, returns the external job id
+    async fn upload(&self, file_path: &str, params: &UploadParams) -> anyhow::Result<String>;
+    async fn status(&self, id: &str) -> anyhow::Result<StatusResponse>;
+    /// Loads one of the result files (see `ASR_FILE_RES`, `ASR_FILE_LAT`)
+    async fn result(&self, id: &str, file_name: &str) -> anyhow::Result<String>;
+    /// Drops the job data from the engine
+    async fn clean(&self, id: &str) -> anyhow::Result<()>;
+}
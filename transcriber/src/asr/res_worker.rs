@@ -1,31 +1,43 @@
 use std::error::Error;
+use std::sync::Arc;
 
-use crate::filer::file::{make_name, Filer};
+use crate::audio::chunk::{ChunkManifest, MANIFEST};
+use crate::data::format::ResultFormat;
+use crate::data::meta::{self, Meta};
+use crate::filer::adder;
+use crate::filer::file::{
+    chunk_folder, make_name, split_path, sub_folder, sub_path, validate_sub_dir,
+};
+use crate::model::models::WorkStatus;
 use crate::{
-    QSender, ASR_FILE_LAT, ASR_FILE_RES, DIR_FAILED, DIR_PROCESSED, DIR_WORKING, INFO_EXTENSION,
+    QSender, StatusTracker, Storage, ASR_FILE_LAT, ASR_FILE_RES, DIR_FAILED, DIR_PROCESSED,
+    DIR_WORKING, INFO_EXTENSION,
 };
 use pgmq::Message;
 use tokio_util::sync::CancellationToken;
 
-use super::client::ASRClient;
-use crate::data::api::{CleanMessage, ResultMessage};
+use super::AsrBackend;
+use crate::data::api::{ChunkRef, CleanMessage, ResultMessage};
 use crate::postgres::queue::PQueue;
+use crate::transcript::{self, lattice, subtitles};
 
 pub struct Worker {
-    filer: Filer,
+    filer: Arc<dyn Storage + Send + Sync>,
     result_queue: PQueue,
     ct: CancellationToken,
-    asr_client: ASRClient,
+    asr_client: Arc<dyn AsrBackend + Send + Sync>,
     clean_queue: Box<dyn QSender<CleanMessage> + Send + Sync>,
+    tracker: Box<dyn StatusTracker + Send + Sync>,
 }
 
 impl Worker {
     pub async fn new(
         ct: CancellationToken,
-        asr_client: ASRClient,
+        asr_client: Arc<dyn AsrBackend + Send + Sync>,
         result_queue: PQueue,
-        filer: Filer,
+        filer: Arc<dyn Storage + Send + Sync>,
         clean_queue: Box<dyn QSender<CleanMessage> + Send + Sync>,
+        tracker: Box<dyn StatusTracker + Send + Sync>,
     ) -> Result<Self, Box<dyn Error + Send + Sync>> {
         log::info!("Init Result Worker");
         Ok(Self {
@@ -34,6 +46,7 @@ impl Worker {
             ct,
             asr_client,
             clean_queue,
+            tracker,
         })
     }
 
@@ -41,6 +54,7 @@ impl Worker {
         crate::postgres::queue::run(
             self.result_queue.clone(),
             |msg: Message<ResultMessage>| async move { self.process_msg(msg).await },
+            |msg: Message<ResultMessage>, err| async move { self.exhausted(msg, &err).await },
             self.ct.clone(),
             "result worker",
         )
@@ -50,26 +64,17 @@ impl Worker {
     pub async fn process_msg(&self, msg: Message<ResultMessage>) -> anyhow::Result<bool> {
         log::info!("Process {:?}", msg);
         let msg_asr = msg.message;
-        if msg.read_ct > 3 {
-            log::warn!("Max retries reached {:?}", msg_asr);
-
-            // don't fail here, just try set error
-            if let Err(err) = self
-                .process_error(
-                    &msg_asr,
-                    format!("Max retries reached {:?}", msg_asr).as_str(),
-                )
-                .await
-            {
-                log::error!("Error processing result message: {}", err);
-            }
-
-            return Ok(true);
-        }
+        validate_sub_dir(&msg_asr.sub_dir)?;
         if !msg_asr.finished {
             log::warn!("Skip non finished event {:?}", msg_asr);
             return Ok(true);
         }
+        if let Some(chunk) = &msg_asr.chunk {
+            self.process_chunk(&msg_asr, chunk).await?;
+            self.send_clean_msg(&msg_asr.external_id).await?;
+            log::info!("done: {}", msg.msg_id);
+            return Ok(true);
+        }
         if let Some(err_str) = &msg_asr.error {
             self.process_error(&msg_asr, err_str).await?;
             return Ok(true);
@@ -79,46 +84,328 @@ impl Worker {
         Ok(true)
     }
 
+    /// Fails the job given up: the audio goes to `failed`, the chunk gets its `.err`
+    pub async fn exhausted(&self, msg: Message<ResultMessage>, error: &str) -> anyhow::Result<()> {
+        log::warn!("Max retries reached {:?}", msg.message);
+        let mut msg_asr = msg.message;
+        validate_sub_dir(&msg_asr.sub_dir)?;
+        let err_str = match &msg_asr.error {
+            Some(err) => format!("{}\nmax retries reached: {}", err, error),
+            None => format!("max retries reached: {}", error),
+        };
+        msg_asr.error = Some(err_str.clone());
+        match &msg_asr.chunk {
+            Some(chunk) => {
+                self.process_chunk(&msg_asr, chunk).await?;
+                self.send_clean_msg(&msg_asr.external_id).await
+            }
+            None => self.process_error(&msg_asr, &err_str).await,
+        }
+    }
+
     async fn process_error(&self, msg_asr: &ResultMessage, err_str: &str) -> anyhow::Result<()> {
         log::info!("Process error {:?}", msg_asr);
-        let f_name = msg_asr.file.clone();
-        let new_f_name = self.filer.non_existing_name(&f_name, DIR_FAILED)?;
-        self.filer
-            .save_txt(&make_name(&new_f_name, ".err"), DIR_FAILED, err_str)?;
+        self.save_error(&msg_asr.id, &msg_asr.file, &msg_asr.sub_dir, err_str)
+            .await?;
+        self.send_clean_msg(&msg_asr.external_id).await
+    }
+
+    /// Moves the audio to `failed` with the error next to it
+    async fn save_error(
+        &self,
+        id: &str,
+        f_name: &str,
+        sub_dir: &str,
+        err_str: &str,
+    ) -> anyhow::Result<()> {
+        let (working, failed) = (
+            sub_folder(DIR_WORKING, sub_dir),
+            sub_folder(DIR_FAILED, sub_dir),
+        );
+        let new_f_name = self.filer.non_existing_name(f_name, &failed).await?;
         self.filer
-            .move_to(&f_name, &new_f_name, DIR_WORKING, DIR_FAILED)?;
-        if let Err(e) = self.filer.move_to(
-            &make_name(&f_name, INFO_EXTENSION),
-            &make_name(&new_f_name, INFO_EXTENSION),
-            DIR_WORKING,
-            DIR_FAILED,
-        ) {
-            log::info!("No info file?: {}", e);
+            .save_txt(&make_name(&new_f_name, ".err"), &failed, err_str)
+            .await?;
+        // the replay of the job finds the audio by the recorded name
+        self.tracker
+            .set_result_file(id, &sub_path(sub_dir, &new_f_name))
+            .await?;
+        adder::move_with_meta(self.filer.as_ref(), f_name, &new_f_name, &working, &failed).await?;
+        // don't fail here, the files are already moved
+        if let Err(err) = self
+            .tracker
+            .set_status(id, WorkStatus::Failed, Some(err_str))
+            .await
+        {
+            log::error!("can't save status: {}", err);
         }
-        self.send_clean_msg(&msg_asr.external_id).await
+        Ok(())
     }
 
     async fn process_success(&self, msg_asr: ResultMessage) -> anyhow::Result<()> {
         log::info!("Process success {:?}", msg_asr);
-        let f_name = msg_asr.file.clone();
+        self.tracker
+            .set_status(&msg_asr.id, WorkStatus::Fetching, None)
+            .await?;
         let res = self.load_res(&msg_asr.external_id, ASR_FILE_RES).await?;
         let res_lat = self.load_res(&msg_asr.external_id, ASR_FILE_LAT).await?;
-        let new_f_name = self.filer.non_existing_name(&f_name, DIR_PROCESSED)?;
-        self.filer
-            .save_txt(&make_name(&new_f_name, ".txt"), DIR_PROCESSED, &res)?;
+        self.save_success(&msg_asr.id, &msg_asr.file, &msg_asr.sub_dir, &res, &res_lat)
+            .await?;
+        self.send_clean_msg(&msg_asr.external_id).await
+    }
+
+    /// Saves the outputs to `processed` and moves the audio there last, so the audio in
+    /// `processed` means the outputs are complete. The result file is recorded before the
+    /// move: a retry after a crash overwrites the outputs as the audio is still in `working`,
+    /// a retry after the move finds the audio by the recorded name and only finishes the job
+    async fn save_success(
+        &self,
+        id: &str,
+        f_name: &str,
+        sub_dir: &str,
+        res: &str,
+        res_lat: &str,
+    ) -> anyhow::Result<()> {
+        let (working, processed) = (
+            sub_folder(DIR_WORKING, sub_dir),
+            sub_folder(DIR_PROCESSED, sub_dir),
+        );
+        if !self.filer.exists(f_name, &working).await? {
+            if let Some(stored) = self.tracker.result_file(id).await? {
+                let (_, name) = split_path(&stored);
+                if self.filer.exists(&name, &processed).await? {
+                    log::warn!("Already in {}: {}", processed, name);
+                    self.set_done(id).await;
+                    return Ok(());
+                }
+            }
+        }
+        let new_f_name = self.filer.non_existing_name(f_name, &processed).await?;
+        let meta = match self.read_meta(f_name, &working).await {
+            Some(v) => Some(v),
+            // moved before the crash of the previous try
+            None => self.read_meta(&new_f_name, &processed).await,
+        };
         self.filer
-            .save_txt(&make_name(&new_f_name, ".lat.txt"), DIR_PROCESSED, &res_lat)?;
+            .save_txt(&make_name(&new_f_name, ".txt"), &processed, res)
+            .await?;
         self.filer
-            .move_to(&f_name, &new_f_name, DIR_WORKING, DIR_PROCESSED)?;
-        if let Err(e) = self.filer.move_to(
-            &make_name(&f_name, INFO_EXTENSION),
-            &make_name(&new_f_name, INFO_EXTENSION),
-            DIR_WORKING,
-            DIR_PROCESSED,
-        ) {
-            log::info!("No info file?: {}", e);
+            .save_txt(&make_name(&new_f_name, ".lat.txt"), &processed, res_lat)
+            .await?;
+        // subtitles are extras, the job still succeeds without them
+        if let Err(err) = self
+            .save_formats(&new_f_name, &processed, res_lat, meta.as_ref())
+            .await
+        {
+            log::error!("can't save subtitles: {}", err);
+        }
+        self.tracker
+            .set_result_file(id, &sub_path(sub_dir, &new_f_name))
+            .await?;
+        adder::move_with_meta(
+            self.filer.as_ref(),
+            f_name,
+            &new_f_name,
+            &working,
+            &processed,
+        )
+        .await?;
+        self.set_done(id).await;
+        Ok(())
+    }
+
+    async fn set_done(&self, id: &str) {
+        // don't fail here, the files are already moved
+        if let Err(err) = self.tracker.set_status(id, WorkStatus::Done, None).await {
+            log::error!("can't save status: {}", err);
         }
-        self.send_clean_msg(&msg_asr.external_id).await
+    }
+
+    /// Keeps the chunk result next to the chunk audio, merges all of them when the last
+    /// chunk is finished
+    async fn process_chunk(&self, msg_asr: &ResultMessage, chunk: &ChunkRef) -> anyhow::Result<()> {
+        log::info!("Process chunk {} of {}", chunk.index, chunk.parent);
+        let folder = chunk_folder(&chunk.parent);
+        let manifest: ChunkManifest = match self.filer.read_txt(MANIFEST, &folder).await {
+            Ok(v) => serde_json::from_str(&v)?,
+            Err(err) => {
+                log::warn!("No chunks of {}, already merged?: {}", chunk.parent, err);
+                return Ok(());
+            }
+        };
+        let part = manifest
+            .chunks
+            .get(chunk.index)
+            .ok_or_else(|| anyhow::anyhow!("no chunk {} of {}", chunk.index, chunk.parent))?;
+        match &msg_asr.error {
+            Some(err_str) => {
+                self.filer
+                    .save_txt(&make_name(&part.file, ".err"), &folder, err_str)
+                    .await?;
+                if let Err(err) = self
+                    .tracker
+                    .set_status(&msg_asr.id, WorkStatus::Failed, Some(err_str))
+                    .await
+                {
+                    log::error!("can't save status: {}", err);
+                }
+            }
+            None => {
+                self.tracker
+                    .set_status(&msg_asr.id, WorkStatus::Fetching, None)
+                    .await?;
+                let res = self.load_res(&msg_asr.external_id, ASR_FILE_RES).await?;
+                let res_lat = self.load_res(&msg_asr.external_id, ASR_FILE_LAT).await?;
+                self.filer
+                    .save_txt(&make_name(&part.file, ".txt"), &folder, &res)
+                    .await?;
+                // written last, marks the chunk as finished
+                self.filer
+                    .save_txt(&make_name(&part.file, ".lat.txt"), &folder, &res_lat)
+                    .await?;
+                if let Err(err) = self
+                    .tracker
+                    .set_status(&msg_asr.id, WorkStatus::Done, None)
+                    .await
+                {
+                    log::error!("can't save status: {}", err);
+                }
+            }
+        }
+        self.merge_chunks(&manifest).await
+    }
+
+    async fn merge_chunks(&self, manifest: &ChunkManifest) -> anyhow::Result<()> {
+        let folder = chunk_folder(&manifest.id);
+        let (mut finished, mut errors) = (0, Vec::new());
+        for part in manifest.chunks.iter() {
+            let err_file = make_name(&part.file, ".err");
+            if self.filer.exists(&err_file, &folder).await? {
+                let err_str = self.filer.read_txt(&err_file, &folder).await?;
+                errors.push(format!("chunk {}: {}", part.file, err_str));
+                finished += 1;
+            } else if self
+                .filer
+                .exists(&make_name(&part.file, ".lat.txt"), &folder)
+                .await?
+            {
+                finished += 1;
+            }
+        }
+        let count = manifest.chunks.len();
+        if finished < count {
+            let progress = (finished * 100 / count) as i32;
+            if let Err(err) = self.tracker.set_progress(&manifest.id, progress).await {
+                log::error!("can't save progress: {}", err);
+            }
+            return Ok(());
+        }
+        // the workers finishing the last chunks at once merge them one by one, the chunk
+        // folder is dropped after the merge
+        let _lock = self.tracker.lock(&manifest.id).await?;
+        if !self.filer.exists(MANIFEST, &folder).await? {
+            log::info!("chunks of {} are already merged", manifest.id);
+            return Ok(());
+        }
+        log::info!("Merge {} chunks of {}", count, manifest.id);
+        let merged = match errors.is_empty() {
+            true => self.read_merged(manifest).await,
+            false => Err(anyhow::anyhow!("{}", errors.join("\n"))),
+        };
+        match merged {
+            Ok((res, res_lat)) => {
+                self.tracker
+                    .set_status(&manifest.id, WorkStatus::Fetching, None)
+                    .await?;
+                self.save_success(
+                    &manifest.id,
+                    &manifest.file,
+                    &manifest.sub_dir,
+                    &res,
+                    &res_lat,
+                )
+                .await?;
+            }
+            Err(err) => {
+                self.save_error(
+                    &manifest.id,
+                    &manifest.file,
+                    &manifest.sub_dir,
+                    &err.to_string(),
+                )
+                .await?
+            }
+        }
+        if let Err(err) = self.filer.delete_dir(&folder).await {
+            log::error!("can't delete chunks: {}", err);
+        }
+        Ok(())
+    }
+
+    /// Joins the chunk texts and lattices, the lattice times are moved by the chunk offsets
+    async fn read_merged(&self, manifest: &ChunkManifest) -> anyhow::Result<(String, String)> {
+        let folder = chunk_folder(&manifest.id);
+        let mut texts = Vec::new();
+        let mut lats = Vec::new();
+        for part in manifest.chunks.iter() {
+            let txt = self
+                .filer
+                .read_txt(&make_name(&part.file, ".txt"), &folder)
+                .await?;
+            texts.push(txt.trim_end().to_string());
+            let lat = self
+                .filer
+                .read_txt(&make_name(&part.file, ".lat.txt"), &folder)
+                .await?;
+            lats.push((part.offset, lat));
+        }
+        let lats: Vec<(f64, &str)> = lats.iter().map(|(o, v)| (*o, v.as_str())).collect();
+        let res_lat = lattice::merge(&lats)
+            .map_err(|err| anyhow::anyhow!("can't merge chunk lattices: {}", err))?;
+        Ok((texts.join("\n") + "\n", res_lat))
+    }
+
+    /// Job metadata from the audio's `.meta`, `None` if there is none or it is broken
+    async fn read_meta(&self, f_name: &str, folder: &str) -> Option<Meta> {
+        let txt = match self
+            .filer
+            .read_txt(&make_name(f_name, INFO_EXTENSION), folder)
+            .await
+        {
+            Ok(v) => v,
+            Err(e) => {
+                log::info!("No info file?: {}", e);
+                return None;
+            }
+        };
+        match meta::read(&txt) {
+            Ok(v) => Some(v),
+            Err(err) => {
+                log::warn!("can't read meta of {}: {}", f_name, err);
+                None
+            }
+        }
+    }
+
+    async fn save_formats(
+        &self,
+        f_name: &str,
+        folder: &str,
+        lat: &str,
+        meta: Option<&Meta>,
+    ) -> anyhow::Result<()> {
+        let transcript = lattice::parse(lat)?;
+        let outputs = [
+            (ResultFormat::Srt, subtitles::to_srt(&transcript)),
+            (ResultFormat::Vtt, subtitles::to_vtt(&transcript)),
+            (ResultFormat::Json, transcript::to_json(&transcript, meta)?),
+        ];
+        for (format, txt) in outputs {
+            self.filer
+                .save_txt(&make_name(f_name, format.extension()), folder, &txt)
+                .await?;
+        }
+        Ok(())
     }
 
     async fn load_res(&self, external_id: &str, file: &str) -> anyhow::Result<String> {
@@ -133,3 +420,559 @@ impl Worker {
             .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Mutex};
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::asr::fake::FakeBackend;
+    use crate::asr::restore::Restorer;
+    use crate::filer::file::Filer;
+    use crate::postgres::work::MemTracker;
+
+    #[derive(Clone, Default)]
+    struct TestSender {
+        sent: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl QSender<CleanMessage> for TestSender {
+        async fn send(&self, data: CleanMessage) -> anyhow::Result<()> {
+            self.sent.lock().unwrap().push(data.external_id);
+            Ok(())
+        }
+    }
+
+    struct TestEnv {
+        dir: tempfile::TempDir,
+        fake: FakeBackend,
+        sender: TestSender,
+        tracker: MemTracker,
+        worker: Worker,
+    }
+
+    async fn make_env() -> TestEnv {
+        let dir = tempfile::tempdir().unwrap();
+        let working = dir.path().join(DIR_WORKING);
+        fs::create_dir_all(&working).unwrap();
+        fs::write(working.join("a.wav"), "audio").unwrap();
+        fs::write(
+            working.join("a.meta"),
+            r#"{"version": 1, "values": {"name": "Olia"}}"#,
+        )
+        .unwrap();
+        let fake = FakeBackend::new();
+        let sender = TestSender::default();
+        let tracker = MemTracker::default();
+        let worker = Worker::new(
+            CancellationToken::new(),
+            Arc::new(fake.clone()),
+            PQueue::new_test("result").await,
+            Arc::new(Filer::new(dir.path().to_str().unwrap())),
+            Box::new(sender.clone()),
+            Box::new(tracker.clone()),
+        )
+        .await
+        .unwrap();
+        TestEnv {
+            dir,
+            fake,
+            sender,
+            tracker,
+            worker,
+        }
+    }
+
+    fn make_msg(error: Option<&str>, read_ct: i32) -> Message<ResultMessage> {
+        Message {
+            msg_id: 1,
+            vt: chrono::Utc::now(),
+            enqueued_at: chrono::Utc::now(),
+            read_ct,
+            message: ResultMessage {
+                id: "1".to_string(),
+                external_id: "ext-1".to_string(),
+                finished: true,
+                file: "a.wav".to_string(),
+                base_dir: "".to_string(),
+                sub_dir: "".to_string(),
+                error: error.map(|e| e.to_string()),
+                chunk: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_success() {
+        let env = make_env().await;
+        env.fake.set_result(ASR_FILE_RES, "olia");
+        env.fake
+            .set_result(ASR_FILE_LAT, "# 1 S0000\n1 0.00 0.50 Olia .\n");
+        assert!(env.worker.process_msg(make_msg(None, 1)).await.unwrap());
+        let processed = env.dir.path().join(DIR_PROCESSED);
+        assert_eq!("olia", fs::read_to_string(processed.join("a.txt")).unwrap());
+        assert_eq!(
+            "# 1 S0000\n1 0.00 0.50 Olia .\n",
+            fs::read_to_string(processed.join("a.lat.txt")).unwrap()
+        );
+        assert_eq!(
+            "1\n00:00:00,000 --> 00:00:00,500\nOlia.\n\n",
+            fs::read_to_string(processed.join("a.srt")).unwrap()
+        );
+        assert!(processed.join("a.vtt").exists());
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(processed.join("a.json")).unwrap()).unwrap();
+        assert_eq!("Olia", json["meta"]["values"]["name"]);
+        assert!(processed.join("a.wav").exists());
+        assert!(processed.join("a.meta").exists());
+        assert!(!env.dir.path().join(DIR_WORKING).join("a.wav").exists());
+        assert_eq!(vec!["ext-1".to_string()], *env.sender.sent.lock().unwrap());
+        assert_eq!(
+            vec![
+                ("1".to_string(), WorkStatus::Fetching),
+                ("1".to_string(), WorkStatus::Done)
+            ],
+            *env.tracker.statuses.lock().unwrap()
+        );
+        assert_eq!(
+            vec![("1".to_string(), "a.wav".to_string())],
+            *env.tracker.result_files.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_success_renamed() {
+        let env = make_env().await;
+        env.fake.set_result(ASR_FILE_RES, "olia");
+        env.fake.set_result(ASR_FILE_LAT, "lat");
+        let processed = env.dir.path().join(DIR_PROCESSED);
+        fs::create_dir_all(&processed).unwrap();
+        fs::write(processed.join("a.wav"), "old").unwrap();
+        assert!(env.worker.process_msg(make_msg(None, 1)).await.unwrap());
+        assert_eq!(
+            "olia",
+            fs::read_to_string(processed.join("a.1.txt")).unwrap()
+        );
+        assert_eq!(
+            vec![("1".to_string(), "a.1.wav".to_string())],
+            *env.tracker.result_files.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_success_retry_after_crash() {
+        let env = make_env().await;
+        env.fake.set_result(ASR_FILE_RES, "olia");
+        env.fake
+            .set_result(ASR_FILE_LAT, "# 1 S0000\n1 0.00 0.50 Olia .\n");
+        let processed = env.dir.path().join(DIR_PROCESSED);
+        fs::create_dir_all(&processed).unwrap();
+        fs::write(processed.join("a.txt"), "ol").unwrap();
+        fs::rename(
+            env.dir.path().join(DIR_WORKING).join("a.meta"),
+            processed.join("a.meta"),
+        )
+        .unwrap();
+        assert!(env.worker.process_msg(make_msg(None, 2)).await.unwrap());
+        assert_eq!("olia", fs::read_to_string(processed.join("a.txt")).unwrap());
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(processed.join("a.json")).unwrap()).unwrap();
+        assert_eq!("Olia", json["meta"]["values"]["name"]);
+        assert!(processed.join("a.wav").exists());
+        assert_eq!(
+            vec![("1".to_string(), "a.wav".to_string())],
+            *env.tracker.result_files.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_success_retry_after_move() {
+        let env = make_env().await;
+        env.fake.set_result(ASR_FILE_RES, "olia");
+        env.fake.set_result(ASR_FILE_LAT, "lat");
+        let (working, processed) = (
+            env.dir.path().join(DIR_WORKING),
+            env.dir.path().join(DIR_PROCESSED),
+        );
+        fs::create_dir_all(&processed).unwrap();
+        // a.wav of an older job, a.1.wav moved by the crashed try
+        fs::write(processed.join("a.wav"), "old").unwrap();
+        fs::write(processed.join("a.1.txt"), "ol").unwrap();
+        fs::write(processed.join("a.1.lat.txt"), "lat").unwrap();
+        fs::rename(working.join("a.meta"), processed.join("a.1.meta")).unwrap();
+        fs::rename(working.join("a.wav"), processed.join("a.1.wav")).unwrap();
+        env.tracker
+            .result_files
+            .lock()
+            .unwrap()
+            .push(("1".to_string(), "a.1.wav".to_string()));
+        assert!(env.worker.process_msg(make_msg(None, 2)).await.unwrap());
+        assert_eq!("ol", fs::read_to_string(processed.join("a.1.txt")).unwrap());
+        assert!(!processed.join("a.2.txt").exists());
+        assert!(!processed.join("a.2.wav").exists());
+        assert_eq!(
+            vec![("1".to_string(), "a.1.wav".to_string())],
+            *env.tracker.result_files.lock().unwrap()
+        );
+        assert_eq!(
+            Some(&("1".to_string(), WorkStatus::Done)),
+            env.tracker.statuses.lock().unwrap().last()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_success_audio_gone() {
+        let env = make_env().await;
+        env.fake.set_result(ASR_FILE_RES, "olia");
+        env.fake.set_result(ASR_FILE_LAT, "lat");
+        let processed = env.dir.path().join(DIR_PROCESSED);
+        fs::create_dir_all(&processed).unwrap();
+        // a.wav of another job
+        fs::write(processed.join("a.wav"), "old").unwrap();
+        fs::write(processed.join("a.lat.txt"), "lat").unwrap();
+        fs::remove_file(env.dir.path().join(DIR_WORKING).join("a.wav")).unwrap();
+        assert!(env.worker.process_msg(make_msg(None, 2)).await.is_err());
+        assert!(!env
+            .tracker
+            .statuses
+            .lock()
+            .unwrap()
+            .contains(&("1".to_string(), WorkStatus::Done)));
+    }
+
+    #[tokio::test]
+    async fn test_success_sub_dir() {
+        let env = make_env().await;
+        env.fake.set_result(ASR_FILE_RES, "olia");
+        env.fake.set_result(ASR_FILE_LAT, "lat");
+        let working = env.dir.path().join(DIR_WORKING).join("team");
+        fs::create_dir_all(&working).unwrap();
+        fs::rename(
+            env.dir.path().join(DIR_WORKING).join("a.wav"),
+            working.join("a.wav"),
+        )
+        .unwrap();
+        let mut msg = make_msg(None, 1);
+        msg.message.sub_dir = "team".to_string();
+        assert!(env.worker.process_msg(msg).await.unwrap());
+        let processed = env.dir.path().join(DIR_PROCESSED).join("team");
+        assert_eq!("olia", fs::read_to_string(processed.join("a.txt")).unwrap());
+        assert!(processed.join("a.wav").exists());
+        assert_eq!(
+            vec![("1".to_string(), "team/a.wav".to_string())],
+            *env.tracker.result_files.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_sub_dir() {
+        let env = make_env().await;
+        let working = env.dir.path().join(DIR_WORKING).join("team");
+        fs::create_dir_all(&working).unwrap();
+        fs::rename(
+            env.dir.path().join(DIR_WORKING).join("a.wav"),
+            working.join("a.wav"),
+        )
+        .unwrap();
+        let mut msg = make_msg(Some("asr failed"), 1);
+        msg.message.sub_dir = "team".to_string();
+        assert!(env.worker.process_msg(msg).await.unwrap());
+        let failed = env.dir.path().join(DIR_FAILED).join("team");
+        assert!(failed.join("a.wav").exists());
+        assert!(failed.join("a.err").exists());
+    }
+
+    #[tokio::test]
+    async fn test_wrong_sub_dir() {
+        let env = make_env().await;
+        let mut msg = make_msg(Some("asr failed"), 1);
+        msg.message.sub_dir = "../..".to_string();
+        assert!(env.worker.process_msg(msg).await.is_err());
+        assert!(env.dir.path().join(DIR_WORKING).join("a.wav").exists());
+    }
+
+    #[tokio::test]
+    async fn test_no_result() {
+        let env = make_env().await;
+        assert!(env.worker.process_msg(make_msg(None, 1)).await.is_err());
+        assert!(env.dir.path().join(DIR_WORKING).join("a.wav").exists());
+        assert!(env.sender.sent.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_success_wrong_lattice() {
+        let env = make_env().await;
+        env.fake.set_result(ASR_FILE_RES, "olia");
+        env.fake.set_result(ASR_FILE_LAT, "lat");
+        assert!(env.worker.process_msg(make_msg(None, 1)).await.unwrap());
+        let processed = env.dir.path().join(DIR_PROCESSED);
+        assert!(processed.join("a.lat.txt").exists());
+        assert!(!processed.join("a.srt").exists());
+        assert_eq!(
+            Some(&("1".to_string(), WorkStatus::Done)),
+            env.tracker.statuses.lock().unwrap().last()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error() {
+        let env = make_env().await;
+        assert!(env
+            .worker
+            .process_msg(make_msg(Some("asr failed"), 1))
+            .await
+            .unwrap());
+        let failed = env.dir.path().join(DIR_FAILED);
+        assert_eq!(
+            "asr failed",
+            fs::read_to_string(failed.join("a.err")).unwrap()
+        );
+        assert!(failed.join("a.wav").exists());
+        assert_eq!(vec!["ext-1".to_string()], *env.sender.sent.lock().unwrap());
+        assert_eq!(
+            vec![("1".to_string(), WorkStatus::Failed)],
+            *env.tracker.statuses.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exhausted() {
+        let env = make_env().await;
+        env.worker
+            .exhausted(make_msg(None, 4), "boom")
+            .await
+            .unwrap();
+        let failed = env.dir.path().join(DIR_FAILED);
+        assert_eq!(
+            "max retries reached: boom",
+            fs::read_to_string(failed.join("a.err")).unwrap()
+        );
+        assert!(failed.join("a.wav").exists());
+        assert_eq!(
+            vec![("1".to_string(), WorkStatus::Failed)],
+            *env.tracker.statuses.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay_exhausted() {
+        let env = make_env().await;
+        let msg = make_msg(None, 4);
+        env.worker.exhausted(msg.clone(), "boom").await.unwrap();
+        let restorer = Restorer::new(
+            Arc::new(Filer::new(env.dir.path().to_str().unwrap())),
+            Box::new(env.tracker.clone()),
+        );
+        restorer
+            .restore(&serde_json::to_value(&msg.message).unwrap())
+            .await
+            .unwrap();
+        let failed = env.dir.path().join(DIR_FAILED);
+        assert!(!failed.join("a.wav").exists());
+        assert!(!failed.join("a.err").exists());
+
+        env.fake.set_result(ASR_FILE_RES, "olia");
+        env.fake.set_result(ASR_FILE_LAT, "lat");
+        assert!(env.worker.process_msg(msg).await.unwrap());
+        let processed = env.dir.path().join(DIR_PROCESSED);
+        assert!(processed.join("a.wav").exists());
+        assert!(processed.join("a.meta").exists());
+        assert_eq!("olia", fs::read_to_string(processed.join("a.txt")).unwrap());
+        assert_eq!(
+            vec![
+                ("1".to_string(), WorkStatus::Failed),
+                ("1".to_string(), WorkStatus::Queued),
+                ("1".to_string(), WorkStatus::Fetching),
+                ("1".to_string(), WorkStatus::Done)
+            ],
+            *env.tracker.statuses.lock().unwrap()
+        );
+        assert_eq!(
+            Some("a.wav".to_string()),
+            env.tracker.result_file("1").await.unwrap()
+        );
+    }
+
+    fn chunk_msg(index: usize, error: Option<&str>) -> Message<ResultMessage> {
+        let mut res = make_msg(error, 1);
+        res.message.id = format!("1.{:03}", index);
+        res.message.external_id = format!("ext-{}", index);
+        res.message.file = format!("{:03}.wav", index);
+        res.message.chunk = Some(ChunkRef {
+            parent: "1".to_string(),
+            index,
+        });
+        res
+    }
+
+    fn make_chunks(env: &TestEnv) -> std::path::PathBuf {
+        let chunks = env.dir.path().join(chunk_folder("1"));
+        fs::create_dir_all(&chunks).unwrap();
+        let manifest = r#"{"id":"1","file":"a.wav","chunks":[
+            {"file":"000.wav","offset":0.0,"duration":4.2},
+            {"file":"001.wav","offset":4.2,"duration":5.8}]}"#;
+        fs::write(chunks.join(MANIFEST), manifest).unwrap();
+        chunks
+    }
+
+    #[tokio::test]
+    async fn test_chunks() {
+        let env = make_env().await;
+        let chunks = make_chunks(&env);
+        env.fake.set_result(ASR_FILE_RES, "olia\n");
+        env.fake
+            .set_result(ASR_FILE_LAT, "# 1 S0000\n1 0.00 0.50 Olia .\n");
+        assert!(env.worker.process_msg(chunk_msg(1, None)).await.unwrap());
+        assert!(chunks.join("001.lat.txt").exists());
+        assert_eq!(
+            vec![("1".to_string(), 50)],
+            *env.tracker.progress.lock().unwrap()
+        );
+        let processed = env.dir.path().join(DIR_PROCESSED);
+        assert!(!processed.join("a.txt").exists());
+
+        assert!(env.worker.process_msg(chunk_msg(0, None)).await.unwrap());
+        assert_eq!(
+            "olia\nolia\n",
+            fs::read_to_string(processed.join("a.txt")).unwrap()
+        );
+        assert_eq!(
+            "# 1 S0000\n1 0.00 0.50 Olia .\n\n# 2 S0000\n1 4.20 4.70 Olia .\n\n",
+            fs::read_to_string(processed.join("a.lat.txt")).unwrap()
+        );
+        assert!(processed.join("a.srt").exists());
+        assert!(processed.join("a.wav").exists());
+        assert!(!chunks.exists());
+        assert_eq!(
+            vec!["ext-1".to_string(), "ext-0".to_string()],
+            *env.sender.sent.lock().unwrap()
+        );
+        assert_eq!(
+            vec![
+                ("1.001".to_string(), WorkStatus::Fetching),
+                ("1.001".to_string(), WorkStatus::Done),
+                ("1.000".to_string(), WorkStatus::Fetching),
+                ("1.000".to_string(), WorkStatus::Done),
+                ("1".to_string(), WorkStatus::Fetching),
+                ("1".to_string(), WorkStatus::Done),
+            ],
+            *env.tracker.statuses.lock().unwrap()
+        );
+        assert_eq!(
+            vec![("1".to_string(), "a.wav".to_string())],
+            *env.tracker.result_files.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunks_error() {
+        let env = make_env().await;
+        let chunks = make_chunks(&env);
+        env.fake.set_result(ASR_FILE_RES, "olia");
+        env.fake.set_result(ASR_FILE_LAT, "lat");
+        assert!(env
+            .worker
+            .process_msg(chunk_msg(0, Some("ERR")))
+            .await
+            .unwrap());
+        assert!(chunks.join("000.err").exists());
+        assert!(env.worker.process_msg(chunk_msg(1, None)).await.unwrap());
+        let failed = env.dir.path().join(DIR_FAILED);
+        assert_eq!(
+            "chunk 000.wav: ERR",
+            fs::read_to_string(failed.join("a.err")).unwrap()
+        );
+        assert!(failed.join("a.wav").exists());
+        assert!(!chunks.exists());
+        assert_eq!(
+            Some(&("1".to_string(), WorkStatus::Failed)),
+            env.tracker.statuses.lock().unwrap().last()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunks_exhausted() {
+        let env = make_env().await;
+        let chunks = make_chunks(&env);
+        env.fake.set_result(ASR_FILE_RES, "olia");
+        env.fake.set_result(ASR_FILE_LAT, "lat");
+        env.worker
+            .exhausted(chunk_msg(0, None), "boom")
+            .await
+            .unwrap();
+        assert!(chunks.join("000.err").exists());
+        assert!(env.worker.process_msg(chunk_msg(1, None)).await.unwrap());
+        let failed = env.dir.path().join(DIR_FAILED);
+        assert_eq!(
+            "chunk 000.wav: max retries reached: boom",
+            fs::read_to_string(failed.join("a.err")).unwrap()
+        );
+        assert!(failed.join("a.wav").exists());
+        assert!(!chunks.exists());
+    }
+
+    #[tokio::test]
+    async fn test_chunks_wrong_lattice() {
+        let env = make_env().await;
+        make_chunks(&env);
+        env.fake.set_result(ASR_FILE_RES, "olia");
+        env.fake.set_result(ASR_FILE_LAT, "# 1 S0000\n1 x 1 a\n");
+        assert!(env.worker.process_msg(chunk_msg(0, None)).await.unwrap());
+        assert!(env.worker.process_msg(chunk_msg(1, None)).await.unwrap());
+        let failed = env.dir.path().join(DIR_FAILED);
+        assert!(fs::read_to_string(failed.join("a.err"))
+            .unwrap()
+            .starts_with("can't merge chunk lattices"));
+    }
+
+    #[tokio::test]
+    async fn test_chunks_merged_at_once() {
+        let env = make_env().await;
+        let chunks = make_chunks(&env);
+        for part in ["000", "001"] {
+            fs::write(chunks.join(format!("{part}.txt")), "olia\n").unwrap();
+            fs::write(
+                chunks.join(format!("{part}.lat.txt")),
+                "# 1 S0000\n1 0.00 0.50 Olia .\n",
+            )
+            .unwrap();
+        }
+        let manifest: ChunkManifest =
+            serde_json::from_str(&fs::read_to_string(chunks.join(MANIFEST)).unwrap()).unwrap();
+        // both workers see all chunks finished before any of them merges
+        let held = env.tracker.lock("1").await.unwrap();
+        let (first, second, _) = tokio::join!(
+            env.worker.merge_chunks(&manifest),
+            env.worker.merge_chunks(&manifest),
+            async {
+                tokio::task::yield_now().await;
+                drop(held);
+            }
+        );
+        first.unwrap();
+        second.unwrap();
+        let processed = env.dir.path().join(DIR_PROCESSED);
+        assert!(processed.join("a.wav").exists());
+        assert!(!processed.join("a.1.txt").exists());
+        assert!(!chunks.exists());
+        assert_eq!(
+            vec![
+                ("1".to_string(), WorkStatus::Fetching),
+                ("1".to_string(), WorkStatus::Done),
+            ],
+            *env.tracker.statuses.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunks_merged() {
+        let env = make_env().await;
+        assert!(env.worker.process_msg(chunk_msg(0, None)).await.unwrap());
+        assert!(env.dir.path().join(DIR_WORKING).join("a.wav").exists());
+        assert_eq!(vec!["ext-0".to_string()], *env.sender.sent.lock().unwrap());
+        assert!(env.tracker.statuses.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,190 @@
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::audio::chunk::MANIFEST;
+use crate::data::api::ChunkRef;
+use crate::filer::adder;
+use crate::filer::file::{chunk_folder, make_name, split_path, sub_folder, validate_sub_dir};
+use crate::{StatusTracker, Storage, DIR_FAILED, DIR_WORKING};
+
+/// Job fields of the input, status and result messages, the clean message has none of them
+#[derive(Deserialize, Debug)]
+struct JobRef {
+    #[serde(default)]
+    id: String,
+    #[serde(default)]
+    file: String,
+    #[serde(default)]
+    sub_dir: String,
+    #[serde(default)]
+    chunk: Option<ChunkRef>,
+}
+
+/// Brings the job given up back to `working` before its dead letter is replayed
+pub struct Restorer {
+    filer: Arc<dyn Storage + Send + Sync>,
+    tracker: Box<dyn StatusTracker + Send + Sync>,
+}
+
+impl Restorer {
+    pub fn new(
+        filer: Arc<dyn Storage + Send + Sync>,
+        tracker: Box<dyn StatusTracker + Send + Sync>,
+    ) -> Self {
+        Self { filer, tracker }
+    }
+
+    /// Moves the failed audio back to `working`, drops its error and restarts the job.
+    /// The audio still in `working` is left as is, so the restore may be repeated
+    pub async fn restore(&self, message: &serde_json::Value) -> anyhow::Result<()> {
+        let job: JobRef = serde_json::from_value(message.clone())?;
+        if job.id.is_empty() {
+            log::debug!("no job to restore");
+            return Ok(());
+        }
+        log::info!("Restore {:?}", job);
+        validate_sub_dir(&job.sub_dir)?;
+        match &job.chunk {
+            Some(chunk) => self.restore_chunk(&job, chunk).await?,
+            None => self.restore_audio(&job).await?,
+        }
+        self.tracker.restart(&job.id).await
+    }
+
+    async fn restore_audio(&self, job: &JobRef) -> anyhow::Result<()> {
+        let (working, failed) = (
+            sub_folder(DIR_WORKING, &job.sub_dir),
+            sub_folder(DIR_FAILED, &job.sub_dir),
+        );
+        if self.filer.exists(&job.file, &working).await? {
+            log::info!("Already in {}: {}", working, job.file);
+            return Ok(());
+        }
+        let name = match self.tracker.result_file(&job.id).await? {
+            Some(stored) => split_path(&stored).1,
+            None => return Err(anyhow::anyhow!("no audio of {} in {}", job.id, working)),
+        };
+        if !self.filer.exists(&name, &failed).await? {
+            return Err(anyhow::anyhow!(
+                "no audio of {} in {} or {}",
+                job.id,
+                working,
+                failed
+            ));
+        }
+        adder::move_back_to_working(self.filer.as_ref(), &name, &job.file, &job.sub_dir).await
+    }
+
+    /// The chunk is transcribed again only while its parent waits for the other chunks
+    async fn restore_chunk(&self, job: &JobRef, chunk: &ChunkRef) -> anyhow::Result<()> {
+        let folder = chunk_folder(&chunk.parent);
+        if !self.filer.exists(MANIFEST, &folder).await? {
+            return Err(anyhow::anyhow!(
+                "job {} of the chunk {} is already finished",
+                chunk.parent,
+                job.id
+            ));
+        }
+        let err_file = make_name(&job.file, ".err");
+        if self.filer.exists(&err_file, &folder).await? {
+            self.filer.delete(&err_file, &folder).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::filer::file::Filer;
+    use crate::model::models::WorkStatus;
+    use crate::postgres::work::MemTracker;
+    use crate::DIR_CHUNKS;
+
+    fn make_restorer(dir: &tempfile::TempDir, tracker: &MemTracker) -> Restorer {
+        Restorer::new(
+            Arc::new(Filer::new(dir.path().to_str().unwrap())),
+            Box::new(tracker.clone()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let failed = dir.path().join(DIR_FAILED).join("team");
+        fs::create_dir_all(&failed).unwrap();
+        fs::write(failed.join("a.1.wav"), "audio").unwrap();
+        fs::write(failed.join("a.1.meta"), "meta").unwrap();
+        fs::write(failed.join("a.1.err"), "boom").unwrap();
+        let tracker = MemTracker::default();
+        tracker.set_result_file("1", "team/a.1.wav").await.unwrap();
+        let msg =
+            serde_json::json!({"id": "1", "file": "a.wav", "base_dir": "/data", "sub_dir": "team"});
+        make_restorer(&dir, &tracker).restore(&msg).await.unwrap();
+        let working = dir.path().join(DIR_WORKING).join("team");
+        assert_eq!("audio", fs::read_to_string(working.join("a.wav")).unwrap());
+        assert_eq!("meta", fs::read_to_string(working.join("a.meta")).unwrap());
+        assert_eq!(0, fs::read_dir(&failed).unwrap().count());
+        assert_eq!(
+            vec![("1".to_string(), WorkStatus::Queued)],
+            *tracker.statuses.lock().unwrap()
+        );
+        assert_eq!(None, tracker.result_file("1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_restore_in_working() {
+        let dir = tempfile::tempdir().unwrap();
+        let working = dir.path().join(DIR_WORKING);
+        fs::create_dir_all(&working).unwrap();
+        fs::write(working.join("a.wav"), "audio").unwrap();
+        let tracker = MemTracker::default();
+        let msg = serde_json::json!({"id": "1", "file": "a.wav", "base_dir": "/data"});
+        make_restorer(&dir, &tracker).restore(&msg).await.unwrap();
+        assert!(working.join("a.wav").exists());
+        assert_eq!(1, tracker.statuses.lock().unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn test_restore_no_audio() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracker = MemTracker::default();
+        tracker.set_result_file("1", "a.wav").await.unwrap();
+        let msg = serde_json::json!({"id": "1", "file": "a.wav", "base_dir": "/data"});
+        assert!(make_restorer(&dir, &tracker).restore(&msg).await.is_err());
+        assert!(tracker.statuses.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_restore_clean() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracker = MemTracker::default();
+        let msg = serde_json::json!({"external_id": "ext-1"});
+        make_restorer(&dir, &tracker).restore(&msg).await.unwrap();
+        assert!(tracker.statuses.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_restore_chunk() {
+        let dir = tempfile::tempdir().unwrap();
+        let chunks = dir.path().join(DIR_WORKING).join(DIR_CHUNKS).join("1");
+        fs::create_dir_all(&chunks).unwrap();
+        fs::write(chunks.join(MANIFEST), "{}").unwrap();
+        fs::write(chunks.join("001.err"), "boom").unwrap();
+        let tracker = MemTracker::default();
+        let msg = serde_json::json!({"id": "1.001", "file": "001.wav", "base_dir": "/data",
+            "chunk": {"parent": "1", "index": 1}});
+        make_restorer(&dir, &tracker).restore(&msg).await.unwrap();
+        assert!(!chunks.join("001.err").exists());
+        assert_eq!(
+            vec![("1.001".to_string(), WorkStatus::Queued)],
+            *tracker.statuses.lock().unwrap()
+        );
+
+        fs::remove_dir_all(&chunks).unwrap();
+        assert!(make_restorer(&dir, &tracker).restore(&msg).await.is_err());
+    }
+}
@@ -0,0 +1,342 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use pgmq::Message;
+use rand::Rng;
+use tokio_util::sync::CancellationToken;
+
+use super::AsrBackend;
+use crate::data::api::{ResultMessage, StatusMessage};
+use crate::postgres::queue::PQueue;
+use crate::{QDelaySender, QSender, StatusTracker};
+
+/// Polls the ASR for the status of the uploaded jobs.
+/// Each message is one status check, unfinished jobs are re-enqueued with a delay,
+/// so a single worker follows any number of the in-flight jobs
+pub struct Worker {
+    queue: PQueue,
+    ct: CancellationToken,
+    asr_client: Arc<dyn AsrBackend + Send + Sync>,
+    status_queue: Box<dyn QDelaySender<StatusMessage> + Send + Sync>,
+    result_queue: Box<dyn QSender<ResultMessage> + Send + Sync>,
+    timeout: Duration,
+    tracker: Box<dyn StatusTracker + Send + Sync>,
+}
+
+impl Worker {
+    pub async fn new(
+        ct: CancellationToken,
+        asr_client: Arc<dyn AsrBackend + Send + Sync>,
+        queue: PQueue,
+        status_queue: Box<dyn QDelaySender<StatusMessage> + Send + Sync>,
+        result_queue: Box<dyn QSender<ResultMessage> + Send + Sync>,
+        timeout: Duration,
+        tracker: Box<dyn StatusTracker + Send + Sync>,
+    ) -> anyhow::Result<Self> {
+        log::info!("Init Status Worker");
+        Ok(Self {
+            queue,
+            ct,
+            asr_client,
+            status_queue,
+            result_queue,
+            timeout,
+            tracker,
+        })
+    }
+
+    pub async fn run(&self) -> anyhow::Result<()> {
+        crate::postgres::queue::run(
+            self.queue.clone(),
+            |msg: Message<StatusMessage>| async move { self.process_msg(msg).await },
+            |msg: Message<StatusMessage>, err| async move { self.exhausted(msg, &err).await },
+            self.ct.clone(),
+            "status worker",
+        )
+        .await
+    }
+
+    /// Sends the failed result for the job given up
+    pub async fn exhausted(&self, msg: Message<StatusMessage>, error: &str) -> anyhow::Result<()> {
+        log::warn!("Max retries reached {:?}", msg.message);
+        self.send_result(&msg.message, &format!("max retries reached: {}", error))
+            .await
+    }
+
+    pub async fn process_msg(&self, msg: Message<StatusMessage>) -> anyhow::Result<bool> {
+        log::info!("Process {:?}", msg);
+        let msg_st = msg.message;
+        match self.get_status(&msg_st.external_id).await {
+            Ok((true, err, _)) => {
+                log::info!("completed: {}", msg_st.external_id);
+                self.send_result(&msg_st, &err).await?;
+            }
+            // the finished job is taken even if late, e.g. the replayed message
+            Ok((false, _, _)) if self.timed_out(&msg_st) => {
+                log::warn!("Status wait timeout {:?}", msg_st);
+                self.send_result(&msg_st, "status wait timeout").await?;
+            }
+            Ok((false, _, progress)) => {
+                if let Some(progress) = progress {
+                    if let Err(err) = self.tracker.set_progress(&msg_st.id, progress as i32).await {
+                        log::error!("can't save progress: {}", err);
+                    }
+                }
+                let mut next = msg_st;
+                next.err_count = 0;
+                self.status_queue.send_delay(next, delay()).await?;
+            }
+            Err(e) => {
+                let mut next = msg_st;
+                next.err_count += 1;
+                log::error!("err {}: {}", next.err_count, e);
+                if next.err_count > 3 {
+                    log::error!("max retries reached");
+                    self.send_result(&next, &e.to_string()).await?;
+                } else {
+                    self.status_queue.send_delay(next, delay()).await?;
+                }
+            }
+        }
+        log::info!("done: {}", msg.msg_id);
+        Ok(true)
+    }
+
+    fn timed_out(&self, msg_st: &StatusMessage) -> bool {
+        let elapsed = (chrono::Utc::now() - msg_st.uploaded)
+            .to_std()
+            .unwrap_or_default();
+        elapsed >= self.timeout
+    }
+
+    async fn get_status(&self, ext_id: &str) -> anyhow::Result<(bool, String, Option<u32>)> {
+        let res = self.asr_client.status(ext_id).await?;
+        log::info!("status: {:?}, progress: {:?}", res.status, res.progress);
+        if let Some(status) = res.status {
+            if status == "COMPLETED" {
+                return Ok((true, "".to_string(), res.progress));
+            }
+        }
+        if let Some(err_code) = res.error_code {
+            return Ok((
+                true,
+                format!(
+                    "{}\n{}",
+                    err_code,
+                    res.error.unwrap_or_else(|| "".to_string())
+                )
+                .to_string(),
+                res.progress,
+            ));
+        }
+        Ok((false, "".to_string(), res.progress))
+    }
+
+    async fn send_result(&self, orig: &StatusMessage, error: &str) -> anyhow::Result<()> {
+        log::info!("send result, id: {}, err: {}", orig.id, error);
+        let res = ResultMessage {
+            id: orig.id.clone(),
+            file: orig.file.clone(),
+            base_dir: orig.base_dir.clone(),
+            sub_dir: orig.sub_dir.clone(),
+            finished: true,
+            external_id: orig.external_id.clone(),
+            error: if error.is_empty() {
+                None
+            } else {
+                Some(error.to_string())
+            },
+            chunk: orig.chunk.clone(),
+        };
+        self.result_queue.send(res).await
+    }
+}
+
+fn delay() -> Duration {
+    let jitter = {
+        let mut rng = rand::thread_rng();
+        Duration::from_millis(rng.gen_range(0..=5000))
+    };
+    Duration::from_secs(8) + jitter
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::asr::fake::FakeBackend;
+    use crate::postgres::work::MemTracker;
+
+    #[derive(Clone, Default)]
+    struct TestSender {
+        statuses: Arc<Mutex<Vec<StatusMessage>>>,
+        results: Arc<Mutex<Vec<ResultMessage>>>,
+    }
+
+    #[async_trait]
+    impl QDelaySender<StatusMessage> for TestSender {
+        async fn send_delay(&self, data: StatusMessage, _delay: Duration) -> anyhow::Result<()> {
+            self.statuses.lock().unwrap().push(data);
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl QSender<ResultMessage> for TestSender {
+        async fn send(&self, data: ResultMessage) -> anyhow::Result<()> {
+            self.results.lock().unwrap().push(data);
+            Ok(())
+        }
+    }
+
+    async fn make_worker(fake: &FakeBackend, sender: &TestSender) -> Worker {
+        make_worker_tracked(fake, sender, &MemTracker::default()).await
+    }
+
+    async fn make_worker_tracked(
+        fake: &FakeBackend,
+        sender: &TestSender,
+        tracker: &MemTracker,
+    ) -> Worker {
+        Worker::new(
+            CancellationToken::new(),
+            Arc::new(fake.clone()),
+            PQueue::new_test("status").await,
+            Box::new(sender.clone()),
+            Box::new(sender.clone()),
+            Duration::from_secs(3600),
+            Box::new(tracker.clone()),
+        )
+        .await
+        .unwrap()
+    }
+
+    fn make_msg(uploaded: chrono::DateTime<chrono::Utc>, read_ct: i32) -> Message<StatusMessage> {
+        Message {
+            msg_id: 1,
+            vt: chrono::Utc::now(),
+            enqueued_at: chrono::Utc::now(),
+            read_ct,
+            message: StatusMessage {
+                id: "1".to_string(),
+                external_id: "ext-1".to_string(),
+                file: "a.wav".to_string(),
+                base_dir: "".to_string(),
+                sub_dir: "".to_string(),
+                uploaded,
+                err_count: 0,
+                chunk: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_completed() {
+        let fake = FakeBackend::new();
+        let sender = TestSender::default();
+        let worker = make_worker(&fake, &sender).await;
+        assert!(worker
+            .process_msg(make_msg(chrono::Utc::now(), 1))
+            .await
+            .unwrap());
+        let results = sender.results.lock().unwrap();
+        assert_eq!(1, results.len());
+        assert!(results[0].finished);
+        assert_eq!(None, results[0].error);
+        assert!(sender.statuses.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_asr_error() {
+        let fake = FakeBackend::new();
+        fake.set_error("ERR", "no audio");
+        let sender = TestSender::default();
+        let worker = make_worker(&fake, &sender).await;
+        assert!(worker
+            .process_msg(make_msg(chrono::Utc::now(), 1))
+            .await
+            .unwrap());
+        let results = sender.results.lock().unwrap();
+        assert_eq!(Some("ERR\nno audio".to_string()), results[0].error);
+    }
+
+    #[tokio::test]
+    async fn test_requeue() {
+        let fake = FakeBackend::new();
+        fake.set_status("Transcription");
+        let sender = TestSender::default();
+        let worker = make_worker(&fake, &sender).await;
+        assert!(worker
+            .process_msg(make_msg(chrono::Utc::now(), 1))
+            .await
+            .unwrap());
+        assert!(sender.results.lock().unwrap().is_empty());
+        let statuses = sender.statuses.lock().unwrap();
+        assert_eq!(1, statuses.len());
+        assert_eq!("ext-1", statuses[0].external_id);
+    }
+
+    #[tokio::test]
+    async fn test_progress() {
+        let fake = FakeBackend::new();
+        fake.set_status("Transcription");
+        fake.set_progress(40);
+        let sender = TestSender::default();
+        let tracker = MemTracker::default();
+        let worker = make_worker_tracked(&fake, &sender, &tracker).await;
+        assert!(worker
+            .process_msg(make_msg(chrono::Utc::now(), 1))
+            .await
+            .unwrap());
+        assert_eq!(
+            vec![("1".to_string(), 40)],
+            *tracker.progress.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let fake = FakeBackend::new();
+        fake.set_status("Transcription");
+        let sender = TestSender::default();
+        let worker = make_worker(&fake, &sender).await;
+        let uploaded = chrono::Utc::now() - chrono::Duration::hours(2);
+        assert!(worker.process_msg(make_msg(uploaded, 1)).await.unwrap());
+        let results = sender.results.lock().unwrap();
+        assert_eq!(Some("status wait timeout".to_string()), results[0].error);
+        assert!(sender.statuses.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_completed_after_timeout() {
+        let fake = FakeBackend::new();
+        let sender = TestSender::default();
+        let worker = make_worker(&fake, &sender).await;
+        let uploaded = chrono::Utc::now() - chrono::Duration::hours(2);
+        assert!(worker.process_msg(make_msg(uploaded, 1)).await.unwrap());
+        let results = sender.results.lock().unwrap();
+        assert_eq!(1, results.len());
+        assert_eq!(None, results[0].error);
+    }
+
+    #[tokio::test]
+    async fn test_exhausted() {
+        let fake = FakeBackend::new();
+        let sender = TestSender::default();
+        let worker = make_worker(&fake, &sender).await;
+        worker
+            .exhausted(make_msg(chrono::Utc::now(), 4), "boom")
+            .await
+            .unwrap();
+        let results = sender.results.lock().unwrap();
+        assert_eq!(1, results.len());
+        assert!(results[0].finished);
+        assert_eq!(
+            Some("max retries reached: boom".to_string()),
+            results[0].error
+        );
+    }
+}
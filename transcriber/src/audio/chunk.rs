@@ -0,0 +1,227 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::decode::{decode, to_i16};
+use super::vad::{energy, FRAME_SECS};
+
+/// Frames averaged around the cut candidate, so a single quiet frame inside speech is not taken
+const SMOOTH_FRAMES: usize = 5;
+/// Chunk list saved next to the chunk files
+pub const MANIFEST: &str = "chunks.json";
+
+/// When and where the long audio is split
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkParams {
+    /// Audio longer than this is split, no chunk is longer, seconds
+    pub max_len: f64,
+    /// The quietest point is searched this far back from `max_len`, seconds
+    pub search: f64,
+}
+
+/// One part of the split audio
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChunkFile {
+    pub file: String,
+    /// Start in the original audio, seconds
+    pub offset: f64,
+    /// Seconds
+    pub duration: f64,
+}
+
+/// Original job of the chunks, everything needed to merge the results
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChunkManifest {
+    pub id: String,
+    pub file: String,
+    #[serde(default)]
+    pub sub_dir: String,
+    pub chunks: Vec<ChunkFile>,
+}
+
+/// Splits `src` at the quiet points into 16 bit PCM wav files in `dir`,
+/// the source sample rate and channels are kept
+pub fn split(src: &Path, dir: &Path, params: &ChunkParams) -> anyhow::Result<Vec<ChunkFile>> {
+    let energy = energy(src)?;
+    let cuts: Vec<f64> = cut_points(&energy, params)
+        .into_iter()
+        .map(|v| v as f64 * FRAME_SECS)
+        .collect();
+    std::fs::create_dir_all(dir)?;
+    let mut res: Vec<ChunkFile> = Vec::new();
+    let mut current: Option<Part> = None;
+    let mut cut_at: Option<u64> = None;
+    let mut pos = 0u64;
+    decode(src, |spec, samples| {
+        let channels = spec.channels.count();
+        for frame in samples.chunks_exact(channels) {
+            if current.is_none() || cut_at == Some(pos) {
+                if let Some(part) = current.take() {
+                    res.push(part.finish(pos)?);
+                }
+                let index = res.len();
+                cut_at = cuts
+                    .get(index)
+                    .map(|v| (v * spec.rate as f64).round() as u64);
+                current = Some(Part::new(dir, index, pos, spec.rate, channels)?);
+            }
+            let part = current.as_mut().unwrap();
+            for v in frame {
+                part.writer.write_sample(to_i16(*v))?;
+            }
+            pos += 1;
+        }
+        Ok(())
+    })?;
+    if let Some(part) = current {
+        res.push(part.finish(pos)?);
+    }
+    Ok(res)
+}
+
+/// Returns the cut positions in the energy frames. Each cut is the quietest point in the last
+/// `search` seconds before the chunk reaches `max_len`, the latest one on a tie
+pub fn cut_points(energy: &[f32], params: &ChunkParams) -> Vec<usize> {
+    let max_len = ((params.max_len / FRAME_SECS).round() as usize).max(1);
+    let search = ((params.search / FRAME_SECS).round() as usize).min(max_len - 1);
+    let smooth = smooth(energy);
+    let mut res = Vec::new();
+    let mut start = 0;
+    while energy.len() - start > max_len {
+        let to = start + max_len;
+        let mut best = to;
+        for i in (to - search..=to).rev() {
+            if smooth[i] < smooth[best] {
+                best = i;
+            }
+        }
+        res.push(best);
+        start = best;
+    }
+    res
+}
+
+fn smooth(energy: &[f32]) -> Vec<f32> {
+    let half = SMOOTH_FRAMES / 2;
+    (0..energy.len())
+        .map(|i| {
+            let from = i.saturating_sub(half);
+            let to = (i + half + 1).min(energy.len());
+            energy[from..to].iter().sum::<f32>() / (to - from) as f32
+        })
+        .collect()
+}
+
+struct Part {
+    writer: hound::WavWriter<BufWriter<File>>,
+    file: String,
+    start: u64,
+    rate: u32,
+}
+
+impl Part {
+    fn new(
+        dir: &Path,
+        index: usize,
+        start: u64,
+        rate: u32,
+        channels: usize,
+    ) -> anyhow::Result<Self> {
+        let file = format!("{:03}.wav", index);
+        let spec = hound::WavSpec {
+            channels: channels as u16,
+            sample_rate: rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        Ok(Self {
+            writer: hound::WavWriter::create(dir.join(&file), spec)?,
+            file,
+            start,
+            rate,
+        })
+    }
+
+    fn finish(self, end: u64) -> anyhow::Result<ChunkFile> {
+        self.writer.finalize()?;
+        Ok(ChunkFile {
+            file: self.file,
+            offset: self.start as f64 / self.rate as f64,
+            duration: (end - self.start) as f64 / self.rate as f64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::probe::tests::wav;
+    use test_case::test_case;
+
+    fn params(max_len: f64, search: f64) -> ChunkParams {
+        ChunkParams { max_len, search }
+    }
+
+    fn quiet(len: usize, from: usize, to: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| if i >= from && i < to { 0.0 } else { 1.0 })
+            .collect()
+    }
+
+    #[test_case(quiet(50, 0, 0), params(6.0, 3.0), vec![]; "short")]
+    #[test_case(quiet(60, 0, 0), params(6.0, 3.0), vec![]; "exact")]
+    #[test_case(quiet(100, 40, 45), params(6.0, 3.0), vec![42]; "silence")]
+    #[test_case(quiet(100, 0, 0), params(6.0, 3.0), vec![60]; "no silence")]
+    #[test_case(quiet(100, 10, 15), params(6.0, 3.0), vec![60]; "silence out of window")]
+    #[test_case(quiet(200, 40, 45), params(6.0, 3.0), vec![42, 102, 162]; "several")]
+    #[test_case(quiet(100, 0, 0), params(6.0, 10.0), vec![60]; "search longer than chunk")]
+    fn test_cut_points(energy: Vec<f32>, params: ChunkParams, expected: Vec<usize>) {
+        assert_eq!(expected, cut_points(&energy, &params));
+    }
+
+    #[test]
+    fn test_split() {
+        let dir = tempfile::tempdir().unwrap();
+        let samples: Vec<i16> = (0..80000)
+            .map(|i| {
+                if (32000..36000).contains(&i) {
+                    0
+                } else {
+                    ((i as f32 * 0.3).sin() * 10000.0) as i16
+                }
+            })
+            .collect();
+        let src = dir.path().join("a.wav");
+        std::fs::write(&src, wav(&samples, 8000)).unwrap();
+        let out = dir.path().join("chunks");
+        let res = split(&src, &out, &params(6.0, 3.0)).unwrap();
+        assert_eq!(
+            vec![
+                ChunkFile {
+                    file: "000.wav".to_string(),
+                    offset: 0.0,
+                    duration: 4.2
+                },
+                ChunkFile {
+                    file: "001.wav".to_string(),
+                    offset: 4.2,
+                    duration: 5.8
+                },
+            ],
+            res
+        );
+        let reader = hound::WavReader::open(out.join("001.wav")).unwrap();
+        assert_eq!(8000, reader.spec().sample_rate);
+        assert_eq!(46400, reader.duration());
+    }
+
+    #[test]
+    fn test_split_short() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("a.wav");
+        std::fs::write(&src, wav(&[100; 8000], 8000)).unwrap();
+        let res = split(&src, dir.path(), &params(6.0, 3.0)).unwrap();
+        assert_eq!(1, res.len());
+        assert_eq!(1.0, res[0].duration);
+    }
+}
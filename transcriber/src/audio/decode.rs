@@ -0,0 +1,81 @@
+use std::{fs::File, path::Path};
+
+use symphonia::core::{
+    audio::{SampleBuffer, SignalSpec},
+    codecs::{DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error,
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+/// Decodes the whole audio track, passes the interleaved samples to `f` packet by packet.
+/// Corrupt packets are skipped, fails if nothing was decoded
+pub fn decode<F>(path: &Path, mut f: F) -> anyhow::Result<()>
+where
+    F: FnMut(&SignalSpec, &[f32]) -> anyhow::Result<()>,
+{
+    let file = File::open(path)?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|v| v.to_str()) {
+        hint.with_extension(ext);
+    }
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|err| anyhow::anyhow!("can't read audio: {}", err))?;
+    let mut format = probed.format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| anyhow::anyhow!("no audio track"))?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|err| anyhow::anyhow!("no decoder: {}", err))?;
+
+    let mut decoded_any = false;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(v) => v,
+            Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(Error::ResetRequired) => break,
+            Err(err) => return Err(anyhow::anyhow!("can't read audio: {}", err)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(v) => v,
+            Err(Error::DecodeError(err)) => {
+                log::warn!("skip packet: {}", err);
+                continue;
+            }
+            Err(err) => return Err(anyhow::anyhow!("can't decode audio: {}", err)),
+        };
+        if decoded.frames() == 0 {
+            continue;
+        }
+        let spec = *decoded.spec();
+        let mut buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buf.copy_interleaved_ref(decoded);
+        f(&spec, buf.samples())?;
+        decoded_any = true;
+    }
+    if !decoded_any {
+        return Err(anyhow::anyhow!("no audio decoded"));
+    }
+    Ok(())
+}
+
+/// Converts the sample to 16 bit PCM
+pub fn to_i16(v: f32) -> i16 {
+    (v.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
@@ -0,0 +1,220 @@
+use std::{io::Read, path::Path};
+
+/// Bytes enough to recognize any of the known formats
+pub const HEAD_SIZE: usize = 64;
+
+pub const DEFAULT_FORMATS: &str = "mp3,wav,m4a";
+
+/// Audio container recognized by the extension and the first bytes
+#[derive(Debug)]
+pub struct AudioFormat {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    sniff: fn(&[u8]) -> bool,
+}
+
+pub static KNOWN: &[AudioFormat] = &[
+    AudioFormat {
+        name: "wav",
+        extensions: &["wav"],
+        sniff: |h| h.len() >= 12 && &h[0..4] == b"RIFF" && &h[8..12] == b"WAVE",
+    },
+    AudioFormat {
+        name: "mp3",
+        extensions: &["mp3"],
+        sniff: |h| h.starts_with(b"ID3") || is_mpeg_frame(h),
+    },
+    AudioFormat {
+        name: "m4a",
+        extensions: &["m4a", "mp4", "m4b"],
+        sniff: |h| h.len() >= 8 && &h[4..8] == b"ftyp",
+    },
+    AudioFormat {
+        name: "ogg",
+        extensions: &["ogg", "oga"],
+        sniff: |h| h.starts_with(b"OggS"),
+    },
+    AudioFormat {
+        name: "opus",
+        extensions: &["opus"],
+        sniff: |h| h.starts_with(b"OggS") && contains(h, b"OpusHead"),
+    },
+    AudioFormat {
+        name: "flac",
+        extensions: &["flac"],
+        sniff: |h| h.starts_with(b"fLaC"),
+    },
+    AudioFormat {
+        name: "webm",
+        extensions: &["webm", "weba"],
+        sniff: |h| h.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]),
+    },
+    AudioFormat {
+        name: "amr",
+        extensions: &["amr"],
+        sniff: |h| h.starts_with(b"#!AMR"),
+    },
+    AudioFormat {
+        name: "aac",
+        extensions: &["aac"],
+        sniff: |h| h.starts_with(b"ID3") || (h.len() >= 2 && h[0] == 0xff && h[1] & 0xf6 == 0xf0),
+    },
+];
+
+/// MPEG audio frame sync with a valid layer
+fn is_mpeg_frame(h: &[u8]) -> bool {
+    h.len() >= 2 && h[0] == 0xff && h[1] & 0xe0 == 0xe0 && h[1] & 0x06 != 0
+}
+
+fn contains(h: &[u8], v: &[u8]) -> bool {
+    h.windows(v.len()).any(|w| w == v)
+}
+
+/// Accepted audio formats
+#[derive(Debug, Clone)]
+pub struct Formats {
+    allowed: Vec<&'static AudioFormat>,
+}
+
+impl Default for Formats {
+    fn default() -> Self {
+        Self::new(DEFAULT_FORMATS).expect("default formats")
+    }
+}
+
+impl Formats {
+    /// Takes a comma separated list of the format names, e.g. `mp3,wav,flac`
+    pub fn new(names: &str) -> anyhow::Result<Self> {
+        let mut allowed = Vec::new();
+        for name in names.split(',').map(|v| v.trim().to_lowercase()) {
+            if name.is_empty() {
+                continue;
+            }
+            let format = KNOWN.iter().find(|f| f.name == name).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "unknown audio format '{}', known: {}",
+                    name,
+                    KNOWN.iter().map(|f| f.name).collect::<Vec<_>>().join(",")
+                )
+            })?;
+            allowed.push(format);
+        }
+        if allowed.is_empty() {
+            return Err(anyhow::anyhow!("no audio formats"));
+        }
+        Ok(Self { allowed })
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.allowed.iter().map(|f| f.name).collect()
+    }
+
+    /// Finds the format by the file extension only
+    pub fn by_extension(&self, path: &Path) -> Option<&'static AudioFormat> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        self.allowed
+            .iter()
+            .find(|f| f.extensions.contains(&ext.as_str()))
+            .copied()
+    }
+
+    pub fn is_audio(&self, path: &Path) -> bool {
+        self.by_extension(path).is_some()
+    }
+
+    /// Checks the extension and that the first bytes match it
+    pub fn check(&self, path: &Path, head: &[u8]) -> anyhow::Result<&'static AudioFormat> {
+        let format = self.by_extension(path).ok_or_else(|| {
+            anyhow::anyhow!("audio expected, supported: {}", self.names().join(", "))
+        })?;
+        if !(format.sniff)(head) {
+            return Err(anyhow::anyhow!("file content is not {} audio", format.name));
+        }
+        Ok(format)
+    }
+
+    pub fn check_file(&self, path: &Path) -> anyhow::Result<&'static AudioFormat> {
+        let mut head = Vec::with_capacity(HEAD_SIZE);
+        std::fs::File::open(path)?
+            .take(HEAD_SIZE as u64)
+            .read_to_end(&mut head)?;
+        self.check(path, &head)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    const ALL: &str = "wav,mp3,m4a,ogg,opus,flac,webm,amr,aac";
+
+    fn head(prefix: &[u8]) -> Vec<u8> {
+        let mut res = prefix.to_vec();
+        res.resize(HEAD_SIZE, 0);
+        res
+    }
+
+    #[test_case("a.wav", b"RIFF\x24\x00\x00\x00WAVEfmt ", "wav"; "wav")]
+    #[test_case("a.MP3", b"ID3\x04\x00", "mp3"; "mp3 id3")]
+    #[test_case("a.mp3", b"\xff\xfb\x90\x64", "mp3"; "mp3 frame")]
+    #[test_case("a.m4a", b"\x00\x00\x00\x20ftypM4A ", "m4a"; "m4a")]
+    #[test_case("a.mp4", b"\x00\x00\x00\x18ftypisom", "m4a"; "mp4")]
+    #[test_case("a.ogg", b"OggS\x00\x02", "ogg"; "ogg")]
+    #[test_case("a.opus", b"OggS\x00\x02\x00\x00\x00\x00\x00\x00\x00\x00\x01\x02\x03\x04\x00\x00\x00\x00\x01\x02\x03\x04\x01\x13OpusHead", "opus"; "opus")]
+    #[test_case("a.flac", b"fLaC\x00\x00\x00\x22", "flac"; "flac")]
+    #[test_case("a.webm", b"\x1a\x45\xdf\xa3\x9f", "webm"; "webm")]
+    #[test_case("a.amr", b"#!AMR\n", "amr"; "amr")]
+    #[test_case("a.aac", b"\xff\xf1\x50\x80", "aac"; "aac")]
+    fn test_check(name: &str, prefix: &[u8], expected: &str) {
+        let formats = Formats::new(ALL).unwrap();
+        let actual = formats.check(Path::new(name), &head(prefix)).unwrap();
+        assert_eq!(expected, actual.name);
+    }
+
+    #[test_case("a.wav", b"ID3\x04\x00"; "renamed mp3")]
+    #[test_case("a.mp3", b"<html>"; "html")]
+    #[test_case("a.m4a", b""; "empty")]
+    #[test_case("a.opus", b"OggS\x00\x02"; "ogg vorbis")]
+    #[test_case("a.flac", b"fLaC\x00"; "not allowed")]
+    #[test_case("a.txt", b"RIFF\x24\x00\x00\x00WAVE"; "extension")]
+    #[test_case("wav", b"RIFF\x24\x00\x00\x00WAVE"; "no extension")]
+    fn test_check_fail(name: &str, prefix: &[u8]) {
+        let formats = Formats::new("wav,mp3,m4a,opus").unwrap();
+        assert!(formats.check(Path::new(name), &head(prefix)).is_err());
+    }
+
+    #[test]
+    fn test_new() {
+        assert_eq!(
+            vec!["mp3", "wav", "m4a"],
+            Formats::new(DEFAULT_FORMATS).unwrap().names()
+        );
+        assert_eq!(
+            vec!["flac", "ogg"],
+            Formats::new(" FLAC, ogg,").unwrap().names()
+        );
+        assert!(Formats::new("wav,doc").is_err());
+        assert!(Formats::new("").is_err());
+    }
+
+    #[test_case("a.wav", true; "wav")]
+    #[test_case("a.MP3", true; "upper")]
+    #[test_case("a.m4a", true; "m4a")]
+    #[test_case("a.flac", false; "not allowed")]
+    #[test_case("a.meta", false; "meta")]
+    #[test_case("wav", false; "no ext")]
+    fn test_is_audio(name: &str, expected: bool) {
+        assert_eq!(expected, Formats::default().is_audio(Path::new(name)));
+    }
+
+    #[test]
+    fn test_check_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.wav");
+        std::fs::write(&path, b"RIFF\x24\x00\x00\x00WAVE").unwrap();
+        assert_eq!("wav", Formats::default().check_file(&path).unwrap().name);
+        std::fs::write(&path, b"olia").unwrap();
+        assert!(Formats::default().check_file(&path).is_err());
+    }
+}
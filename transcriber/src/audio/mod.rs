@@ -0,0 +1,6 @@
+pub mod chunk;
+pub mod decode;
+pub mod format;
+pub mod normalize;
+pub mod probe;
+pub mod vad;
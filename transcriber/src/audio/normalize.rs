@@ -0,0 +1,232 @@
+use std::{fs::File, path::Path};
+
+use rubato::{FftFixedIn, Resampler};
+
+use super::decode::{decode, to_i16};
+
+/// Frames passed to the resampler at once
+const CHUNK_SIZE: usize = 4096;
+
+/// Target audio for the recognizer upload
+#[derive(Debug, Clone, PartialEq)]
+pub struct NormalizeParams {
+    pub sample_rate: u32,
+    /// 1 - downmix to mono, 0 - keep the source channels
+    pub channels: u16,
+}
+
+impl Default for NormalizeParams {
+    fn default() -> Self {
+        Self {
+            sample_rate: 16000,
+            channels: 1,
+        }
+    }
+}
+
+/// Decodes `src` and writes it as 16 bit PCM wav to `dst` with the target sample rate and channels
+pub fn normalize(src: &Path, dst: &Path, params: &NormalizeParams) -> anyhow::Result<()> {
+    let mut output: Option<Output> = None;
+    decode(src, |spec, samples| {
+        let out = match output.as_mut() {
+            Some(v) => v,
+            None => output.insert(Output::new(dst, spec.rate, spec.channels.count(), params)?),
+        };
+        out.push(samples)
+    })?;
+    match output {
+        Some(v) => v.finish(),
+        None => Err(anyhow::anyhow!("no audio decoded")),
+    }
+}
+
+/// Downmixes, resamples and writes the decoded samples
+struct Output {
+    writer: hound::WavWriter<std::io::BufWriter<File>>,
+    resampler: Option<FftFixedIn<f32>>,
+    in_channels: usize,
+    channels: usize,
+    pending: Vec<Vec<f32>>,
+    /// Resampler output frames still to drop
+    delay: usize,
+    /// Expected output frames
+    expected: u64,
+    written: u64,
+    ratio: f64,
+}
+
+impl Output {
+    fn new(
+        dst: &Path,
+        rate: u32,
+        in_channels: usize,
+        params: &NormalizeParams,
+    ) -> anyhow::Result<Self> {
+        let channels = match params.channels {
+            0 => in_channels,
+            1 => 1,
+            v => return Err(anyhow::anyhow!("wrong channels {}, expected 0 or 1", v)),
+        };
+        let spec = hound::WavSpec {
+            channels: channels as u16,
+            sample_rate: params.sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let resampler = if rate != params.sample_rate {
+            Some(FftFixedIn::<f32>::new(
+                rate as usize,
+                params.sample_rate as usize,
+                CHUNK_SIZE,
+                2,
+                channels,
+            )?)
+        } else {
+            None
+        };
+        Ok(Self {
+            writer: hound::WavWriter::create(dst, spec)?,
+            delay: resampler.as_ref().map(|v| v.output_delay()).unwrap_or(0),
+            resampler,
+            in_channels,
+            channels,
+            pending: vec![Vec::new(); channels],
+            expected: 0,
+            written: 0,
+            ratio: params.sample_rate as f64 / rate as f64,
+        })
+    }
+
+    fn push(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        for frame in samples.chunks_exact(self.in_channels) {
+            if self.channels == 1 {
+                let sum: f32 = frame.iter().sum();
+                self.pending[0].push(sum / self.in_channels as f32);
+            } else {
+                for (ch, v) in frame.iter().enumerate() {
+                    self.pending[ch].push(*v);
+                }
+            }
+        }
+        self.expected += (samples.len() / self.in_channels) as u64;
+        if self.resampler.is_none() {
+            let pending = std::mem::replace(&mut self.pending, vec![Vec::new(); self.channels]);
+            return self.write(&pending);
+        }
+        while self.pending[0].len() >= CHUNK_SIZE {
+            let chunk: Vec<Vec<f32>> = self
+                .pending
+                .iter_mut()
+                .map(|v| v.drain(..CHUNK_SIZE).collect())
+                .collect();
+            let out = self.resampler.as_mut().unwrap().process(&chunk, None)?;
+            self.write(&out)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> anyhow::Result<()> {
+        if let Some(mut resampler) = self.resampler.take() {
+            let expected = (self.expected as f64 * self.ratio).round() as u64;
+            let mut rest = Some(std::mem::take(&mut self.pending));
+            // flush the remaining input and the resampler delay
+            while self.written < expected {
+                let out = resampler.process_partial(rest.take().as_deref(), None)?;
+                if out[0].is_empty() {
+                    break;
+                }
+                let left = expected - self.written + self.delay as u64;
+                let out: Vec<Vec<f32>> = out
+                    .into_iter()
+                    .map(|mut v| {
+                        v.truncate(left.min(v.len() as u64) as usize);
+                        v
+                    })
+                    .collect();
+                self.write(&out)?;
+            }
+        }
+        self.writer.finalize()?;
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[Vec<f32>]) -> anyhow::Result<()> {
+        let skip = self.delay.min(data[0].len());
+        self.delay -= skip;
+        for i in skip..data[0].len() {
+            for ch in data.iter() {
+                self.writer.write_sample(to_i16(ch[i]))?;
+            }
+        }
+        self.written += (data[0].len() - skip) as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::probe::{probe_file, tests::wav};
+
+    fn sine(len: usize, rate: u32) -> Vec<i16> {
+        (0..len)
+            .map(|i| {
+                let t = i as f32 / rate as f32;
+                ((t * 440.0 * std::f32::consts::TAU).sin() * 10000.0) as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_normalize_resample() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("a.wav");
+        std::fs::write(&src, wav(&sine(44100 * 2, 44100), 44100)).unwrap();
+        let dst = dir.path().join("b.wav");
+        normalize(&src, &dst, &NormalizeParams::default()).unwrap();
+        let reader = hound::WavReader::open(&dst).unwrap();
+        assert_eq!(16000, reader.spec().sample_rate);
+        assert_eq!(1, reader.spec().channels);
+        assert_eq!(32000, reader.duration());
+        let info = probe_file(&dst).unwrap().unwrap();
+        assert!((info.duration - 2.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_normalize_wrong_channels() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("a.wav");
+        std::fs::write(&src, wav(&sine(1000, 16000), 16000)).unwrap();
+        let params = NormalizeParams {
+            channels: 2,
+            ..Default::default()
+        };
+        let err = normalize(&src, &dir.path().join("b.wav"), &params).unwrap_err();
+        assert_eq!("wrong channels 2, expected 0 or 1", err.to_string());
+    }
+
+    #[test]
+    fn test_normalize_same_rate() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("a.wav");
+        let samples = sine(1000, 16000);
+        std::fs::write(&src, wav(&samples, 16000)).unwrap();
+        let dst = dir.path().join("b.wav");
+        normalize(&src, &dst, &NormalizeParams::default()).unwrap();
+        let mut reader = hound::WavReader::open(&dst).unwrap();
+        let res: Vec<i16> = reader.samples::<i16>().map(|v| v.unwrap()).collect();
+        assert_eq!(samples.len(), res.len());
+        for (a, b) in samples.iter().zip(res.iter()) {
+            assert!((a - b).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn test_normalize_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("a.wav");
+        std::fs::write(&src, b"not audio at all").unwrap();
+        let dst = dir.path().join("b.wav");
+        assert!(normalize(&src, &dst, &NormalizeParams::default()).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use std::{fs::File, path::Path};
+
+use serde::{Deserialize, Serialize};
+use symphonia::core::{
+    codecs::{DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error,
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+/// Packets decoded to make sure the audio is not corrupt
+const CHECK_PACKETS: usize = 5;
+
+/// Audio parameters found at the ingestion
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AudioInfo {
+    /// Seconds
+    pub duration: f64,
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub codec: String,
+}
+
+/// Reads the audio parameters. Returns `None` if the container is not supported by the decoder,
+/// fails on zero length or corrupt audio
+pub fn probe_file(path: &Path) -> anyhow::Result<Option<AudioInfo>> {
+    let file = File::open(path)?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|v| v.to_str()) {
+        hint.with_extension(ext);
+    }
+    let probed = match symphonia::default::get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    ) {
+        Ok(v) => v,
+        Err(Error::Unsupported(err)) => {
+            log::warn!("can't probe {}: {}", path.display(), err);
+            return Ok(None);
+        }
+        Err(err) => return Err(anyhow::anyhow!("can't read audio: {}", err)),
+    };
+    let mut format = probed.format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| anyhow::anyhow!("no audio track"))?;
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+    let codecs = symphonia::default::get_codecs();
+    let codec = codecs
+        .get_codec(params.codec)
+        .map(|v| v.short_name.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    // without a decoder the length and the parameters from the container are still fine
+    let mut decoder = match codecs.make(&params, &DecoderOptions::default()) {
+        Ok(v) => Some(v),
+        Err(err) => {
+            log::warn!("no decoder for {}: {}", codec, err);
+            None
+        }
+    };
+    let mut sample_rate = params.sample_rate.unwrap_or_default();
+    let mut channels = params
+        .channels
+        .map(|v| v.count() as u32)
+        .unwrap_or_default();
+    let (mut frames, mut tried, mut decoded) = (0u64, 0, 0);
+    loop {
+        let packet = match format.next_packet() {
+            Ok(v) => v,
+            Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(Error::ResetRequired) => break,
+            Err(err) => return Err(anyhow::anyhow!("can't read audio: {}", err)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        frames += packet.dur;
+        if let Some(dec) = decoder.as_mut() {
+            if tried < CHECK_PACKETS {
+                tried += 1;
+                match dec.decode(&packet) {
+                    Ok(buf) => {
+                        decoded += 1;
+                        sample_rate = buf.spec().rate;
+                        channels = buf.spec().channels.count() as u32;
+                    }
+                    Err(Error::DecodeError(err)) => log::debug!("decode error: {}", err),
+                    Err(err) => return Err(anyhow::anyhow!("can't decode audio: {}", err)),
+                }
+            }
+        }
+        if tried >= CHECK_PACKETS && decoded == 0 {
+            return Err(anyhow::anyhow!("can't decode audio"));
+        }
+        // the container knows the length, no need to read further
+        if params.n_frames.is_some() && (decoder.is_none() || decoded > 0) {
+            break;
+        }
+    }
+    if tried > 0 && decoded == 0 {
+        return Err(anyhow::anyhow!("can't decode audio"));
+    }
+    let frames = params.n_frames.unwrap_or(frames);
+    let duration = match (params.time_base, sample_rate) {
+        (Some(tb), _) => {
+            let t = tb.calc_time(frames);
+            t.seconds as f64 + t.frac
+        }
+        (None, rate) if rate > 0 => frames as f64 / rate as f64,
+        _ => 0.0,
+    };
+    if duration <= 0.0 {
+        return Err(anyhow::anyhow!("empty audio"));
+    }
+    Ok(Some(AudioInfo {
+        duration,
+        sample_rate,
+        channels,
+        codec,
+    }))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// 16 bit PCM mono wav
+    pub(crate) fn wav(samples: &[i16], rate: u32) -> Vec<u8> {
+        let data_len = (samples.len() * 2) as u32;
+        let mut res = Vec::new();
+        res.extend_from_slice(b"RIFF");
+        res.extend_from_slice(&(36 + data_len).to_le_bytes());
+        res.extend_from_slice(b"WAVEfmt ");
+        res.extend_from_slice(&16u32.to_le_bytes());
+        res.extend_from_slice(&1u16.to_le_bytes());
+        res.extend_from_slice(&1u16.to_le_bytes());
+        res.extend_from_slice(&rate.to_le_bytes());
+        res.extend_from_slice(&(rate * 2).to_le_bytes());
+        res.extend_from_slice(&2u16.to_le_bytes());
+        res.extend_from_slice(&16u16.to_le_bytes());
+        res.extend_from_slice(b"data");
+        res.extend_from_slice(&data_len.to_le_bytes());
+        for s in samples {
+            res.extend_from_slice(&s.to_le_bytes());
+        }
+        res
+    }
+
+    fn probe_bytes(name: &str, data: &[u8]) -> anyhow::Result<Option<AudioInfo>> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        std::fs::write(&path, data).unwrap();
+        probe_file(&path)
+    }
+
+    #[test]
+    fn test_probe_wav() {
+        let samples = vec![100i16; 16000 * 2];
+        let actual = probe_bytes("a.wav", &wav(&samples, 16000))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            AudioInfo {
+                duration: 2.0,
+                sample_rate: 16000,
+                channels: 1,
+                codec: "pcm_s16le".to_string(),
+            },
+            actual
+        );
+    }
+
+    #[test]
+    fn test_probe_empty() {
+        assert!(probe_bytes("a.wav", &wav(&[], 16000)).is_err());
+    }
+
+    #[test]
+    fn test_probe_corrupt() {
+        let mut data = wav(&[1, 2, 3], 16000);
+        data.truncate(20);
+        assert!(probe_bytes("a.wav", &data).is_err());
+        assert!(probe_bytes("a.mp3", b"ID3\x04\x00\x00\x00\x00\x00\x00olia").is_err());
+    }
+
+    #[test]
+    fn test_probe_unsupported() {
+        assert_eq!(None, probe_bytes("a.amr", b"#!AMR\n\x3c\x00\x00").unwrap());
+    }
+}
@@ -0,0 +1,121 @@
+use std::path::Path;
+
+use super::decode::decode;
+
+/// Energy is measured in frames of this length, seconds
+pub const FRAME_SECS: f64 = 0.1;
+
+/// When the audio has too little speech to send it to the recognizer
+#[derive(Debug, Clone, PartialEq)]
+pub struct VadParams {
+    /// Frames louder than this are taken as speech, dBFS
+    pub threshold: f32,
+    /// Audio with less speech is skipped, seconds
+    pub min_speech: f64,
+}
+
+/// Returns the skip reason if the audio has less speech than `min_speech`
+pub fn check(src: &Path, params: &VadParams) -> anyhow::Result<Option<String>> {
+    let speech = speech_secs(&energy(src)?, params.threshold);
+    log::debug!("speech {}: {:.1}s", src.display(), speech);
+    if speech >= params.min_speech {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "no speech: {:.1}s above {} dBFS, required {:.1}s",
+        speech, params.threshold, params.min_speech
+    )))
+}
+
+/// Seconds of the frames louder than `threshold` dBFS
+pub fn speech_secs(energy: &[f32], threshold: f32) -> f64 {
+    let min_rms = 10f32.powf(threshold / 20.0);
+    energy.iter().filter(|v| **v > min_rms).count() as f64 * FRAME_SECS
+}
+
+/// Mono RMS of each frame
+pub fn energy(src: &Path) -> anyhow::Result<Vec<f32>> {
+    let mut res = Vec::new();
+    let (mut sum, mut count) = (0.0f64, 0usize);
+    decode(src, |spec, samples| {
+        let channels = spec.channels.count();
+        let frame_len = ((spec.rate as f64 * FRAME_SECS) as usize).max(1);
+        for frame in samples.chunks_exact(channels) {
+            let v = frame.iter().sum::<f32>() as f64 / channels as f64;
+            sum += v * v;
+            count += 1;
+            if count == frame_len {
+                res.push((sum / count as f64).sqrt() as f32);
+                (sum, count) = (0.0, 0);
+            }
+        }
+        Ok(())
+    })?;
+    if count > 0 {
+        res.push((sum / count as f64).sqrt() as f32);
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::probe::tests::wav;
+    use test_case::test_case;
+
+    fn params(threshold: f32, min_speech: f64) -> VadParams {
+        VadParams {
+            threshold,
+            min_speech,
+        }
+    }
+
+    #[test_case(&[], -40.0, 0.0; "empty")]
+    #[test_case(&[0.0, 0.001, 0.5], -40.0, 0.1; "one loud")]
+    #[test_case(&[0.0, 0.02, 0.5], -40.0, 0.2; "two loud")]
+    #[test_case(&[0.0, 0.02, 0.5], -20.0, 0.1; "higher threshold")]
+    fn test_speech_secs(energy: &[f32], threshold: f32, expected: f64) {
+        assert!((expected - speech_secs(energy, threshold)).abs() < 1e-9);
+    }
+
+    /// 1 s of silence, then `loud` s of a tone at about -10 dBFS
+    fn audio(dir: &Path, loud: f32) -> std::path::PathBuf {
+        let samples: Vec<i16> = (0..(8000.0 * (1.0 + loud)) as usize)
+            .map(|i| match i < 8000 {
+                true => 0,
+                false => ((i as f32 * 0.3).sin() * 10000.0) as i16,
+            })
+            .collect();
+        let res = dir.join("a.wav");
+        std::fs::write(&res, wav(&samples, 8000)).unwrap();
+        res
+    }
+
+    #[test_case(0.0, params(-40.0, 0.5), true; "silence")]
+    #[test_case(0.3, params(-40.0, 0.5), true; "short noise")]
+    #[test_case(1.0, params(-40.0, 0.5), false; "speech")]
+    #[test_case(1.0, params(-5.0, 0.5), true; "quiet for threshold")]
+    #[test_case(0.3, params(-40.0, 0.0), false; "no minimum")]
+    fn test_check(loud: f32, params: VadParams, skip: bool) {
+        let dir = tempfile::tempdir().unwrap();
+        let res = check(&audio(dir.path(), loud), &params).unwrap();
+        assert_eq!(skip, res.is_some(), "{:?}", res);
+    }
+
+    #[test]
+    fn test_check_reason() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            Some("no speech: 0.0s above -40 dBFS, required 0.5s".to_string()),
+            check(&audio(dir.path(), 0.0), &params(-40.0, 0.5)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_check_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("a.wav");
+        std::fs::write(&src, "audio").unwrap();
+        assert!(check(&src, &params(-40.0, 0.5)).is_err());
+    }
+}
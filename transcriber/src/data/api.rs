@@ -1,10 +1,54 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::audio::probe::AudioInfo;
+
 #[derive(Serialize, Debug, Deserialize, Clone)]
 pub struct ASRMessage {
     pub id: String,
     pub file: String,
     pub base_dir: String,
+    /// Path relative to the top dir, kept in working, processed and failed
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub sub_dir: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speakers: Option<u32>,
+    /// Extra recognizer parameters from the `asr_*` meta values
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub office: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio: Option<AudioInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<ChunkRef>,
+}
+
+/// Part of a long audio transcribed as a separate job
+#[derive(Serialize, Debug, Deserialize, Clone, PartialEq)]
+pub struct ChunkRef {
+    /// Id of the original job
+    pub parent: String,
+    pub index: usize,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct StatusMessage {
+    pub id: String,
+    pub external_id: String,
+    pub file: String,
+    pub base_dir: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub sub_dir: String,
+    pub uploaded: DateTime<Utc>,
+    #[serde(default)]
+    pub err_count: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<ChunkRef>,
 }
 
 #[derive(Serialize, Debug, Deserialize, Clone)]
@@ -14,7 +58,11 @@ pub struct ResultMessage {
     pub finished: bool,
     pub file: String,
     pub base_dir: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub sub_dir: String,
     pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<ChunkRef>,
 }
 
 #[derive(Serialize, Debug, Deserialize, Clone)]
@@ -0,0 +1,237 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+/// Keys the server adds to the metadata itself
+const RESERVED: [&str; 2] = ["file", "time"];
+
+/// Upload form fields, the values are checked on the upload and kept in the `.meta` file.
+///
+/// Loaded from the JSON file, e.g.
+/// `{"fields": [{"name": "office", "type": "string", "required": true, "values": ["Vilnius"]}]}`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FormSchema {
+    pub fields: Vec<Field>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    /// Caption for the GUI, the name is shown if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(rename = "type", default)]
+    pub kind: FieldType,
+    #[serde(default)]
+    pub required: bool,
+    /// Allowed values, any value if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<String>,
+    /// Max value length in chars
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    #[default]
+    String,
+    Integer,
+    Boolean,
+}
+
+impl Default for FormSchema {
+    /// The fields of the upload GUI
+    fn default() -> Self {
+        let field = |name: &str, kind: FieldType, required: bool, max_length: usize| Field {
+            name: name.to_string(),
+            label: None,
+            kind,
+            required,
+            values: vec![],
+            max_length: Some(max_length),
+        };
+        Self {
+            fields: vec![
+                field("name", FieldType::String, true, 200),
+                field("office", FieldType::String, true, 200),
+                field("speakers", FieldType::Integer, true, 3),
+                field("model", FieldType::String, false, 50),
+            ],
+        }
+    }
+}
+
+impl FormSchema {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let txt = std::fs::read_to_string(path)
+            .map_err(|err| anyhow::anyhow!("can't read schema {}: {}", path, err))?;
+        Self::from_json(&txt).map_err(|err| anyhow::anyhow!("wrong schema {}: {}", path, err))
+    }
+
+    pub fn from_json(txt: &str) -> anyhow::Result<Self> {
+        let res: FormSchema = serde_json::from_str(txt)?;
+        let mut names = HashSet::new();
+        for field in res.fields.iter() {
+            if field.name.is_empty() || field.name != field.name.trim().to_lowercase() {
+                return Err(anyhow::anyhow!("wrong field name '{}'", field.name));
+            }
+            if RESERVED.contains(&field.name.as_str()) {
+                return Err(anyhow::anyhow!("field name '{}' is reserved", field.name));
+            }
+            if !names.insert(field.name.as_str()) {
+                return Err(anyhow::anyhow!("duplicate field '{}'", field.name));
+            }
+            for v in field.values.iter() {
+                field
+                    .check(v)
+                    .map_err(|err| anyhow::anyhow!("wrong allowed value: {}", err))?;
+            }
+        }
+        Ok(res)
+    }
+
+    /// Checks the form values, returns the trimmed non empty values of the schema fields.
+    /// Fields not in the schema are dropped
+    pub fn validate(
+        &self,
+        values: &HashMap<String, String>,
+    ) -> anyhow::Result<BTreeMap<String, String>> {
+        let mut res = BTreeMap::new();
+        for field in self.fields.iter() {
+            let value = values
+                .get(&field.name)
+                .map(|v| v.trim())
+                .filter(|v| !v.is_empty());
+            match value {
+                Some(v) => {
+                    field.check(v)?;
+                    res.insert(field.name.clone(), v.to_string());
+                }
+                None if field.required => {
+                    return Err(anyhow::anyhow!("no {}", field.name));
+                }
+                None => {}
+            }
+        }
+        for name in values.keys().filter(|k| !res.contains_key(*k)) {
+            log::debug!("skip field not in schema: {}", name);
+        }
+        Ok(res)
+    }
+}
+
+impl Field {
+    fn check(&self, v: &str) -> anyhow::Result<()> {
+        let type_ok = match self.kind {
+            FieldType::String => true,
+            FieldType::Integer => v.parse::<i64>().is_ok(),
+            FieldType::Boolean => v == "true" || v == "false",
+        };
+        if !type_ok {
+            return Err(anyhow::anyhow!("wrong {}", self.name));
+        }
+        if self.max_length.is_some_and(|max| v.chars().count() > max) {
+            return Err(anyhow::anyhow!("{} is too long", self.name));
+        }
+        if !self.values.is_empty() && !self.values.iter().any(|a| a == v) {
+            return Err(anyhow::anyhow!("{} is not allowed", self.name));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn values(items: &[(&str, &str)]) -> HashMap<String, String> {
+        items
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    const SCHEMA: &str = r#"{"fields": [
+        {"name": "office", "label": "Office", "required": true, "values": ["Vilnius", "Kaunas"]},
+        {"name": "case", "type": "string", "max_length": 5},
+        {"name": "speakers", "type": "integer"},
+        {"name": "urgent", "type": "boolean"}
+    ]}"#;
+
+    #[test]
+    fn test_from_json() {
+        let actual = FormSchema::from_json(SCHEMA).unwrap();
+        assert_eq!(4, actual.fields.len());
+        assert_eq!(FieldType::String, actual.fields[0].kind);
+        assert_eq!(Some("Office".to_string()), actual.fields[0].label);
+        assert!(actual.fields[0].required);
+        assert!(!actual.fields[1].required);
+        assert_eq!(Some(5), actual.fields[1].max_length);
+        assert_eq!(FieldType::Boolean, actual.fields[3].kind);
+    }
+
+    #[test_case("{"; "broken")]
+    #[test_case(r#"{"fields": [{"name": ""}]}"#; "empty name")]
+    #[test_case(r#"{"fields": [{"name": "Office"}]}"#; "upper case")]
+    #[test_case(r#"{"fields": [{"name": "file"}]}"#; "reserved")]
+    #[test_case(r#"{"fields": [{"name": "a"}, {"name": "a"}]}"#; "duplicate")]
+    #[test_case(r#"{"fields": [{"name": "a", "type": "date"}]}"#; "type")]
+    #[test_case(r#"{"fields": [{"name": "a", "type": "integer", "values": ["x"]}]}"#; "allowed value type")]
+    fn test_from_json_fail(txt: &str) {
+        assert!(FormSchema::from_json(txt).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema = FormSchema::from_json(SCHEMA).unwrap();
+        let actual = schema
+            .validate(&values(&[
+                ("office", " Kaunas "),
+                ("case", ""),
+                ("speakers", "2"),
+                ("other", "x"),
+            ]))
+            .unwrap();
+        assert_eq!(
+            BTreeMap::from([
+                ("office".to_string(), "Kaunas".to_string()),
+                ("speakers".to_string(), "2".to_string()),
+            ]),
+            actual
+        );
+    }
+
+    #[test_case(&[], "no office"; "required")]
+    #[test_case(&[("office", " ")], "no office"; "required empty")]
+    #[test_case(&[("office", "Klaipėda")], "office is not allowed"; "not allowed")]
+    #[test_case(&[("office", "Kaunas"), ("case", "abcdef")], "case is too long"; "too long")]
+    #[test_case(&[("office", "Kaunas"), ("speakers", "du")], "wrong speakers"; "integer")]
+    #[test_case(&[("office", "Kaunas"), ("urgent", "yes")], "wrong urgent"; "boolean")]
+    fn test_validate_fail(items: &[(&str, &str)], expected: &str) {
+        let schema = FormSchema::from_json(SCHEMA).unwrap();
+        let err = schema.validate(&values(items)).unwrap_err();
+        assert_eq!(expected, err.to_string());
+    }
+
+    #[test]
+    fn test_max_length_chars() {
+        let schema = FormSchema::from_json(SCHEMA).unwrap();
+        assert!(schema
+            .validate(&values(&[("office", "Kaunas"), ("case", "ąčęėį")]))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_default() {
+        let schema = FormSchema::default();
+        let err = schema
+            .validate(&values(&[("name", "Olia"), ("office", "Vilnius")]))
+            .unwrap_err();
+        assert_eq!("no speakers", err.to_string());
+        let json = serde_json::to_string(&schema).unwrap();
+        assert_eq!(schema, FormSchema::from_json(&json).unwrap());
+    }
+}
@@ -0,0 +1,70 @@
+use std::str::FromStr;
+
+/// Transcription output files saved in `processed` next to the audio
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultFormat {
+    Txt,
+    Lat,
+    Srt,
+    Vtt,
+    Json,
+}
+
+impl ResultFormat {
+    /// File extension, pass to `make_name`
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ResultFormat::Txt => ".txt",
+            ResultFormat::Lat => ".lat.txt",
+            ResultFormat::Srt => ".srt",
+            ResultFormat::Vtt => ".vtt",
+            ResultFormat::Json => ".json",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ResultFormat::Txt | ResultFormat::Lat => "text/plain; charset=utf-8",
+            ResultFormat::Srt => "application/x-subrip; charset=utf-8",
+            ResultFormat::Vtt => "text/vtt; charset=utf-8",
+            ResultFormat::Json => "application/json",
+        }
+    }
+}
+
+impl FromStr for ResultFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "txt" => Ok(ResultFormat::Txt),
+            "lat" => Ok(ResultFormat::Lat),
+            "srt" => Ok(ResultFormat::Srt),
+            "vtt" => Ok(ResultFormat::Vtt),
+            "json" => Ok(ResultFormat::Json),
+            _ => Err(anyhow::anyhow!("unknown format '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filer::file::make_name;
+    use test_case::test_case;
+
+    #[test_case("txt", "a.txt"; "txt")]
+    #[test_case("LAT", "a.lat.txt"; "lat")]
+    #[test_case("srt", "a.srt"; "srt")]
+    #[test_case("vtt", "a.vtt"; "vtt")]
+    #[test_case("json", "a.json"; "json")]
+    fn test_name(format: &str, expected: &str) {
+        let f: ResultFormat = format.parse().unwrap();
+        assert_eq!(expected, make_name("a.wav", f.extension()));
+    }
+
+    #[test]
+    fn test_unknown() {
+        assert!("doc".parse::<ResultFormat>().is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+pub const KEY_SPEAKERS: &str = "speakers";
+pub const KEY_MODEL: &str = "model";
+pub const KEY_OFFICE: &str = "office";
+/// Prefix of the keys passed on to the recognizer, e.g. `asr_skip_punctuation`
+pub const ASR_PREFIX: &str = "asr_";
+
+/// Version of the `.meta` JSON written now, 0 - the legacy `Key : value` text
+pub const VERSION: u32 = 1;
+
+/// Job metadata kept in the `.meta` file next to the audio
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Meta {
+    pub version: u32,
+    /// Lowercase keys, e.g. `file`, `time`, `name`, `office`, `speakers`, `model`
+    #[serde(default)]
+    pub values: BTreeMap<String, String>,
+}
+
+impl Meta {
+    /// Current version metadata, the keys are lowercased and the empty values dropped
+    pub fn new<I: IntoIterator<Item = (String, String)>>(values: I) -> Self {
+        Self {
+            version: VERSION,
+            values: values
+                .into_iter()
+                .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+                .filter(|(k, v)| !k.is_empty() && !v.is_empty())
+                .collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Reads the `.meta` file, the JSON document or the legacy `Key : value` lines
+pub fn read(txt: &str) -> anyhow::Result<Meta> {
+    if !txt.trim_start().starts_with('{') {
+        return Ok(Meta {
+            version: 0,
+            values: parse_legacy(txt).into_iter().collect(),
+        });
+    }
+    let res: Meta =
+        serde_json::from_str(txt).map_err(|err| anyhow::anyhow!("wrong meta: {}", err))?;
+    if res.version > VERSION {
+        log::warn!("meta version {} is newer than {}", res.version, VERSION);
+    }
+    Ok(Meta {
+        version: res.version,
+        ..Meta::new(res.values)
+    })
+}
+
+/// Reads the `.meta` file into a map with lowercase keys, a broken file gives no values
+pub fn parse(txt: &str) -> HashMap<String, String> {
+    match read(txt) {
+        Ok(v) => v.values.into_iter().collect(),
+        Err(err) => {
+            log::warn!("{}", err);
+            HashMap::new()
+        }
+    }
+}
+
+fn parse_legacy(txt: &str) -> HashMap<String, String> {
+    txt.lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+        .filter(|(k, _)| !k.is_empty())
+        .collect()
+}
+
+/// Extracts a positive speaker count, empty or invalid values are ignored
+pub fn speakers(values: &HashMap<String, String>) -> Option<u32> {
+    values
+        .get(KEY_SPEAKERS)
+        .and_then(|v| v.parse::<u32>().ok())
+        .filter(|v| *v > 0)
+}
+
+/// Extracts a recognizer name, `None` if not set
+pub fn model(values: &HashMap<String, String>) -> Option<String> {
+    values.get(KEY_MODEL).filter(|v| !v.is_empty()).cloned()
+}
+
+/// Extracts the office name, `None` if not set
+pub fn office(values: &HashMap<String, String>) -> Option<String> {
+    values.get(KEY_OFFICE).filter(|v| !v.is_empty()).cloned()
+}
+
+/// Checks that the recognizer name is safe to pass to the ASR
+pub fn validate_model(model: &str) -> anyhow::Result<()> {
+    if model.is_empty()
+        || model.len() > 50
+        || !model
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    {
+        return Err(anyhow::anyhow!("wrong model '{}'", model));
+    }
+    Ok(())
+}
+
+/// Extracts the recognizer parameters, the keys go without the `asr_` prefix
+pub fn asr_params(values: &HashMap<String, String>) -> BTreeMap<String, String> {
+    values
+        .iter()
+        .filter_map(|(k, v)| k.strip_prefix(ASR_PREFIX).map(|k| (k, v)))
+        .filter(|(k, v)| {
+            let ok = is_param_name(k) && !v.is_empty();
+            if !ok {
+                log::warn!("skip recognizer param '{}'", k);
+            }
+            ok
+        })
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+/// Checks the parameter name is safe to pass as a multipart field name
+pub fn is_param_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 50
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test]
+    fn test_parse() {
+        let actual = parse(
+            "File     : a.wav\nTime     : 2024-08-12 10:11:12\nName     : Olia\nSpeakers : 2\n",
+        );
+        assert_eq!(Some(&"a.wav".to_string()), actual.get("file"));
+        assert_eq!(Some(&"2024-08-12 10:11:12".to_string()), actual.get("time"));
+        assert_eq!(Some(&"Olia".to_string()), actual.get("name"));
+        assert_eq!(Some(&"2".to_string()), actual.get("speakers"));
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let actual = parse(r#"{"version": 1, "values": {"file": "a.wav", "Speakers": "2"}}"#);
+        assert_eq!(Some(&"a.wav".to_string()), actual.get("file"));
+        assert_eq!(Some(2), speakers(&actual));
+    }
+
+    #[test_case("{"; "broken")]
+    #[test_case(r#"{"values": {}}"#; "no version")]
+    #[test_case(r#"{"version": 1, "values": {"a": 1}}"#; "not a string")]
+    fn test_parse_json_fail(txt: &str) {
+        assert!(read(txt).is_err());
+        assert!(parse(txt).is_empty());
+    }
+
+    #[test]
+    fn test_read_legacy() {
+        let actual = read("File     : a.wav\nOffice   : \nSpeakers : 2\n").unwrap();
+        assert_eq!(0, actual.version);
+        assert_eq!(Some(&"a.wav".to_string()), actual.values.get("file"));
+        assert_eq!(Some(&"".to_string()), actual.values.get("office"));
+    }
+
+    #[test]
+    fn test_read_newer() {
+        let actual = read(r#"{"version": 7, "values": {"name": "Olia"}, "extra": true}"#).unwrap();
+        assert_eq!(7, actual.version);
+        assert_eq!(Some(&"Olia".to_string()), actual.values.get("name"));
+    }
+
+    #[test]
+    fn test_to_json() {
+        let meta = Meta::new([
+            ("Name".to_string(), " Olia ".to_string()),
+            ("office".to_string(), "".to_string()),
+            ("speakers".to_string(), "2".to_string()),
+        ]);
+        let txt = meta.to_json().unwrap();
+        assert_eq!(
+            serde_json::json!({"version": 1, "values": {"name": "Olia", "speakers": "2"}}),
+            serde_json::from_str::<serde_json::Value>(&txt).unwrap()
+        );
+        assert_eq!(meta, read(&txt).unwrap());
+    }
+
+    #[test_case("Speakers : 2", Some(2); "number")]
+    #[test_case("Speakers : 0", None; "zero")]
+    #[test_case("Speakers : ", None; "empty")]
+    #[test_case("Speakers : du", None; "text")]
+    #[test_case("Name : olia", None; "missing")]
+    fn test_speakers(txt: &str, expected: Option<u32>) {
+        assert_eq!(expected, speakers(&parse(txt)));
+    }
+
+    #[test_case("Model : ben", Some("ben"); "set")]
+    #[test_case("Model : ", None; "empty")]
+    #[test_case("Name : olia", None; "missing")]
+    fn test_model(txt: &str, expected: Option<&str>) {
+        assert_eq!(expected.map(|v| v.to_string()), model(&parse(txt)));
+    }
+
+    #[test_case("ben", true; "simple")]
+    #[test_case("lt-med_2.1", true; "symbols")]
+    #[test_case("", false; "empty")]
+    #[test_case("ben x", false; "space")]
+    #[test_case("../ben", false; "path")]
+    fn test_validate_model(model: &str, expected: bool) {
+        assert_eq!(expected, validate_model(model).is_ok());
+    }
+
+    #[test]
+    fn test_asr_params() {
+        let actual = asr_params(&parse(
+            "Asr_Skip_Punctuation : true\nasr_ : x\nasr_a b : x\nasr_empty : \nName : olia",
+        ));
+        assert_eq!(
+            BTreeMap::from([("skip_punctuation".to_string(), "true".to_string())]),
+            actual
+        );
+    }
+}
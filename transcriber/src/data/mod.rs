@@ -1 +1,4 @@
 pub mod api;
+pub mod form;
+pub mod format;
+pub mod meta;
@@ -0,0 +1,141 @@
+use clap::{Parser, Subcommand};
+use deadpool_diesel::postgres::{Manager, Pool};
+use deadpool_diesel::Runtime;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use transcriber::asr::restore::Restorer;
+use transcriber::filer::storage::{self, Dirs};
+use transcriber::postgres::{dead_letter::DeadLetters, work::WorkStore};
+use transcriber::INPUT_QUEUE;
+
+/// Inspect and replay the messages that failed in the queues
+#[derive(Parser, Debug)]
+#[command(version = env!("CARGO_APP_VERSION"), name = "dead-letter", about, long_about = None)]
+struct Args {
+    /// Postgres SQL (QUEUE) connection string
+    #[arg(short, long, env)]
+    postgres_url: String,
+
+    /// Queue name: asr_input, asr_status, asr_result, asr_clean
+    #[arg(short, long, env, default_value = INPUT_QUEUE)]
+    queue: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List dead letters
+    List {
+        /// Max messages to show
+        #[arg(short, long, default_value = "50")]
+        limit: i64,
+    },
+    /// Show one dead letter with the full message
+    Show { id: i64 },
+    /// Send messages back to the queue, the failed audio of the job goes back to working
+    Replay {
+        /// Message id
+        id: Option<i64>,
+        /// Replay all messages
+        #[arg(long, conflicts_with = "id", required_unless_present = "id")]
+        all: bool,
+        /// Base working dir of the workers
+        #[arg(short, long, env)]
+        base_dir: String,
+        #[command(flatten)]
+        dirs: Dirs,
+    },
+    /// Drop messages
+    Purge {
+        /// Message id
+        id: Option<i64>,
+        /// Drop all messages
+        #[arg(long, conflicts_with = "id", required_unless_present = "id")]
+        all: bool,
+    },
+}
+
+async fn main_int(args: Args) -> anyhow::Result<()> {
+    log::info!("Starting dead letter tool");
+    log::info!("Version      : {}", env!("CARGO_APP_VERSION"));
+    log::info!("Queue        : {}", args.queue);
+    log::info!("Connecting to postgres...");
+    let dl = DeadLetters::new(&args.postgres_url, &args.queue)
+        .await
+        .map_err(anyhow::Error::msg)?;
+    match args.command {
+        Command::List { limit } => {
+            let items = dl.list(limit).await?;
+            for item in &items {
+                let dead = &item.message;
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    item.msg_id,
+                    dead.failed_at.format("%Y-%m-%d %H:%M:%S"),
+                    dead.read_ct,
+                    first_line(&dead.error)
+                );
+            }
+            println!("Total: {}", items.len());
+        }
+        Command::Show { id } => match dl.get(id).await? {
+            Some(item) => {
+                let dead = &item.message;
+                println!("Id       : {}", item.msg_id);
+                println!("Queue    : {}", dead.queue);
+                println!("Queue id : {}", dead.msg_id);
+                println!("Read     : {}", dead.read_ct);
+                println!("Enqueued : {}", dead.enqueued_at);
+                println!("Failed   : {}", dead.failed_at);
+                println!("Error    :\n{}", dead.error);
+                println!(
+                    "Message  :\n{}",
+                    serde_json::to_string_pretty(&dead.message)?
+                );
+            }
+            None => return Err(anyhow::anyhow!("no message {}", id)),
+        },
+        Command::Replay {
+            id,
+            all: _,
+            base_dir,
+            dirs,
+        } => {
+            let manager = Manager::new(args.postgres_url, Runtime::Tokio1);
+            let pool = Pool::builder(manager).max_size(1).build()?;
+            let restorer = Restorer::new(
+                storage::open(&base_dir, &dirs)?,
+                Box::new(WorkStore::new(pool)),
+            );
+            let restorer = &restorer;
+            let res = dl
+                .replay(id, |msg| async move { restorer.restore(&msg).await })
+                .await?;
+            println!("Replayed: {}", res);
+        }
+        Command::Purge { id, all: _ } => {
+            let res = dl.purge(id).await?;
+            println!("Purged: {}", res);
+        }
+    }
+    Ok(())
+}
+
+fn first_line(s: &str) -> &str {
+    s.lines().next().unwrap_or_default()
+}
+
+#[tokio::main(flavor = "multi_thread", worker_threads = 1)]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::from_default_env())
+        .with(tracing_subscriber::fmt::Layer::default().compact())
+        .init();
+    let args = Args::parse();
+    if let Err(e) = main_int(args).await {
+        log::error!("{}", e);
+        return Err(e);
+    }
+    Ok(())
+}
@@ -1,18 +1,30 @@
-use std::path::PathBuf;
-use transcriber::filer::file::{make_name, Filer};
-use transcriber::postgres::queue::PQueue;
-use transcriber::{data::api::ASRMessage, DIR_WORKING};
+use std::path::Path;
+use std::time::Duration;
+
+use deadpool_diesel::postgres::{Manager, Pool};
+use deadpool_diesel::Runtime;
+use notify::{EventKind, RecursiveMode, Watcher};
+use tokio::time::Instant;
+use transcriber::audio::format::{Formats, DEFAULT_FORMATS};
+use transcriber::audio::probe::{self, AudioInfo};
+use transcriber::data::api::ASRMessage;
+use transcriber::data::meta;
+use transcriber::filer::file::{split_path, validate_sub_dir, Filer};
+use transcriber::filer::{adder, incoming, storage};
+use transcriber::postgres::{incoming::IncomingStore, queue::PQueue};
 
 use clap::Parser;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use transcriber::{QSender, DIR_INCOMING, INFO_EXTENSION, INPUT_QUEUE};
-use ulid::Ulid;
+use transcriber::{shutdown_signal, QSender, DIR_INCOMING, DIR_WORKING, INPUT_QUEUE};
+
+/// Quiet time after the last change in incoming before the scan
+const DEBOUNCE: Duration = Duration::from_secs(2);
 
 /// Add audio task to to transcription queue
 #[derive(Parser, Debug)]
 #[command(version = env!("CARGO_APP_VERSION"), name = "file-adder", about, long_about = None)]
 struct Args {
-    /// File name
+    /// File name, relative to incoming
     #[arg(short, long, env)]
     file: Option<String>,
 
@@ -35,6 +47,35 @@ struct Args {
     /// Send all files from incoming
     #[arg(long, env, default_value = "false")]
     auto: bool,
+
+    /// ASR recognizer. Overridden by the incoming folder config and the file's meta
+    #[arg(short, long, env)]
+    model: Option<String>,
+
+    /// Keep running and send files as they appear in incoming
+    #[arg(long, env, default_value = "false", conflicts_with_all = ["file", "only_msg"])]
+    watch: bool,
+
+    /// Full incoming rescan interval in seconds for the watch mode
+    #[arg(long, env, default_value = "60")]
+    rescan: u64,
+
+    /// Accepted audio formats, comma separated
+    #[arg(long, env = "AUDIO_FORMATS", default_value = DEFAULT_FORMATS)]
+    formats: String,
+
+    /// Seconds a file must stay unchanged between the scans before it is sent
+    #[arg(long, env, default_value = "10")]
+    stable: u64,
+
+    #[command(flatten)]
+    dirs: storage::Dirs,
+}
+
+/// Incoming dir scan state
+struct Scanner {
+    store: IncomingStore,
+    formats: Formats,
 }
 
 async fn main_int(args: Args) -> anyhow::Result<()> {
@@ -42,37 +83,45 @@ async fn main_int(args: Args) -> anyhow::Result<()> {
     log::info!("Version      : {}", env!("CARGO_APP_VERSION"));
     log::info!("Base dir     : {}", args.base_dir);
     let file = args.file.clone().unwrap_or("".to_string());
+    check_local(&args)?;
+    let f = storage::local(&args.base_dir, &args.dirs);
     if args.auto {
-        log::info!(
-            "Add all      : {}",
-            args.base_dir.clone() + "/" + DIR_INCOMING
-        );
+        log::info!("Add all      : {}", f.dir(DIR_INCOMING)?.display());
     }
     log::info!("Connecting to postgres...");
     let pq = PQueue::new(&args.postgres_url, INPUT_QUEUE)
         .await
         .map_err(anyhow::Error::msg)?;
     let sender = Box::new(pq) as Box<dyn QSender<ASRMessage>>;
-    let f = Filer::new(&args.base_dir);
+    let manager = Manager::new(args.postgres_url.clone(), Runtime::Tokio1);
+    let pool = Pool::builder(manager).max_size(2).build()?;
+    let scanner = Scanner {
+        store: IncomingStore::new(pool),
+        formats: Formats::new(&args.formats)?,
+    };
+    log::info!("Formats      : {:?}", scanner.formats.names());
+    if args.watch {
+        return watch(sender.as_ref(), &f, &scanner, &args).await;
+    }
+    if let Some(m) = &args.model {
+        meta::validate_model(m)?;
+    }
+    log::info!("Model        : {:?}", args.model);
     let added = if args.auto {
-        add_files(
-            sender.as_ref(),
-            &f,
-            &args.base_dir,
-            &args.server_base_dir,
-            args.only_msg,
-        )
-        .await?
+        let (added, waiting) = add_files(sender.as_ref(), &f, &scanner, &args).await?;
+        if waiting > 0 {
+            log::info!("{} files are not stable yet", waiting);
+        }
+        added
     } else {
-        add_file(
-            sender.as_ref(),
-            &f,
-            &file,
-            &args.base_dir,
-            &args.server_base_dir,
-            args.only_msg,
-        )
-        .await?
+        let folder = if args.only_msg {
+            DIR_WORKING
+        } else {
+            DIR_INCOMING
+        };
+        let path = f.dir(folder)?.join(&file);
+        let audio = inspect(&scanner.formats, &path).await?;
+        add_file(sender.as_ref(), &f, &file, &args, audio).await?
     };
     if added == 0 {
         log::warn!("No files to transcribe");
@@ -82,70 +131,198 @@ async fn main_int(args: Args) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// The files are moved on the local disk only, the S3 storage gets the audio by the upload
+fn check_local(args: &Args) -> anyhow::Result<()> {
+    for dir in [&args.base_dir, &args.server_base_dir] {
+        if dir.starts_with(storage::S3_SCHEME) {
+            return Err(anyhow::anyhow!(
+                "{dir} is not supported, file-adder works with the local base dir only, \
+                 upload the audio to the sound-keeper for the S3 storage"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Scans incoming on the file system events and periodically, until the shutdown signal
+async fn watch(
+    sender: &dyn QSender<ASRMessage>,
+    f: &Filer,
+    scanner: &Scanner,
+    args: &Args,
+) -> anyhow::Result<()> {
+    let dir = f.dir(DIR_INCOMING)?;
+    log::info!("Watching     : {}", dir.display());
+    log::info!("Rescan every : {}s", args.rescan);
+    let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        match res {
+            Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                // a full channel already means a scan is due
+                _ = tx.try_send(());
+            }
+            Ok(_) => {}
+            Err(e) => log::error!("watch error: {}", e),
+        }
+    })?;
+    watcher.watch(&dir, RecursiveMode::Recursive)?;
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let mut rescan = tokio::time::interval(Duration::from_secs(args.rescan.max(1)));
+    let stable = Duration::from_secs(args.stable);
+    let mut pending = false;
+    // set when some files wait to become stable
+    let mut recheck: Option<Instant> = None;
+    loop {
+        let recheck_at = recheck.unwrap_or_else(Instant::now);
+        let waiting = tokio::select! {
+            _ = &mut shutdown => break,
+            ev = rx.recv() => match ev {
+                Some(()) => {
+                    pending = true;
+                    continue;
+                }
+                None => return Err(anyhow::anyhow!("file watcher stopped")),
+            },
+            _ = tokio::time::sleep(DEBOUNCE), if pending => scan(sender, f, scanner, args).await,
+            _ = tokio::time::sleep_until(recheck_at), if recheck.is_some() => {
+                scan(sender, f, scanner, args).await
+            }
+            _ = rescan.tick() => scan(sender, f, scanner, args).await,
+        };
+        pending = false;
+        recheck = (waiting > 0).then(|| Instant::now() + stable.max(DEBOUNCE));
+    }
+    log::info!("Bye");
+    Ok(())
+}
+
+/// One pass over incoming, errors are logged so the watch keeps running.
+/// Returns the number of files waiting to become stable
+async fn scan(
+    sender: &dyn QSender<ASRMessage>,
+    f: &Filer,
+    scanner: &Scanner,
+    args: &Args,
+) -> usize {
+    match add_files(sender, f, scanner, args).await {
+        Ok((added, waiting)) => {
+            if added > 0 {
+                log::info!("Sent {} files to transcribe", added);
+            }
+            waiting
+        }
+        Err(e) => {
+            log::error!("scan failed: {}", e);
+            0
+        }
+    }
+}
+
+/// Checks the format and probes the audio, fails if the file is not usable audio
+async fn inspect(formats: &Formats, path: &Path) -> anyhow::Result<Option<AudioInfo>> {
+    formats.check_file(path)?;
+    let path = path.to_path_buf();
+    let res = tokio::task::spawn_blocking(move || probe::probe_file(&path)).await??;
+    log::info!("Audio        : {:?}", res);
+    Ok(res)
+}
+
 async fn add_file(
     sender: &dyn QSender<ASRMessage>,
     f: &Filer,
     file: &str,
-    base_dir: &str,
-    server_base_dir: &str,
-    only_msg: bool,
+    args: &Args,
+    audio: Option<AudioInfo>,
 ) -> anyhow::Result<i64> {
     log::info!("Add file     : {}", file);
-    let mut new_f_name = file.to_string();
-    if !only_msg {
-        new_f_name = f.non_existing_name(file, DIR_WORKING)?;
-        f.move_to(file, &new_f_name, DIR_INCOMING, DIR_WORKING)?;
-        if let Err(e) = f.move_to(
-            &make_name(file, INFO_EXTENSION),
-            &make_name(&new_f_name, INFO_EXTENSION),
-            DIR_INCOMING,
-            DIR_WORKING,
-        ) {
-            log::info!("No info file?: {}", e);
-        }
+    let (sub_dir, file) = split_path(file);
+    validate_sub_dir(&sub_dir)?;
+    // the folder config of the file's sub dir wins over the command line
+    let model = adder::folder_model(f, &sub_dir)
+        .await
+        .or(args.model.clone());
+    if let Some(m) = &model {
+        meta::validate_model(m)?;
+    }
+    let new_f_name = if !args.only_msg {
+        adder::move_to_working(f, &file, &sub_dir).await?
     } else {
         log::warn!("Skip copying file");
-    }
-    let ulid = Ulid::new();
-    let mut s_dir = server_base_dir;
+        file
+    };
+    let mut s_dir = args.server_base_dir.as_str();
     if s_dir.is_empty() {
-        s_dir = base_dir;
+        s_dir = &args.base_dir;
     }
     sender
-        .send(ASRMessage {
-            file: new_f_name,
-            id: ulid.to_string(),
-            base_dir: s_dir.to_string(),
-        })
+        .send(adder::make_message(f, &new_f_name, &sub_dir, s_dir, model.as_deref(), audio).await)
         .await?;
     Ok(1)
 }
 
+/// Sends the incoming files unchanged since the previous scans.
+/// A failed file is logged and left for the next scan.
+/// Returns the number of sent files and the number of files not stable yet
 async fn add_files(
     sender: &dyn QSender<ASRMessage>,
     f: &Filer,
-    base_dir: &str,
-    server_base_dir: &str,
-    only_msg: bool,
-) -> anyhow::Result<i64> {
-    let mut source_path = PathBuf::from(base_dir);
-    source_path.extend(&[DIR_INCOMING]);
+    scanner: &Scanner,
+    args: &Args,
+) -> anyhow::Result<(i64, usize)> {
+    let source_path = f.dir(DIR_INCOMING)?;
     log::info!("checking dir     : {}", source_path.display());
+    let key = source_path.to_string_lossy().to_string();
+    let store = &scanner.store;
+    let files = incoming::list_audio(&source_path, &scanner.formats)?;
+    let known = store.list(&key).await?;
+    let check = incoming::check(
+        &key,
+        &files,
+        &known,
+        chrono::Utc::now().naive_utc(),
+        Duration::from_secs(args.stable),
+    );
+    for file in &check.changed {
+        log::info!("not stable       : {}", file.name);
+    }
+    let waiting = check.changed.len() + check.waiting.len();
+    store.save(check.changed).await?;
+    store.delete(&key, check.gone).await?;
     let mut res = 0;
-    for entry in std::fs::read_dir(source_path)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(ext) = path.extension() {
-                let ext_str = ext.to_str().unwrap_or("").to_lowercase();
-                if ext_str == "mp3" || ext_str == "wav" || ext_str == "m4a" {
-                    let file = path.file_name().unwrap().to_str().unwrap();
-                    res += add_file(sender, f, file, base_dir, server_base_dir, only_msg).await?;
+    for file in check.ready {
+        match add_ready(sender, f, scanner, args, &source_path, &file).await {
+            Ok(added) => {
+                res += added;
+                if let Err(e) = store.delete(&key, vec![file.clone()]).await {
+                    log::error!("can't forget {}: {}", file, e);
                 }
             }
+            Err(e) => log::error!("can't add {}: {}", file, e),
+        }
+    }
+    Ok((res, waiting))
+}
+
+/// Sends the stable incoming file, the unusable audio goes to failed
+async fn add_ready(
+    sender: &dyn QSender<ASRMessage>,
+    f: &Filer,
+    scanner: &Scanner,
+    args: &Args,
+    source_path: &Path,
+    file: &str,
+) -> anyhow::Result<i64> {
+    match inspect(&scanner.formats, &source_path.join(file)).await {
+        Ok(audio) => add_file(sender, f, file, args, audio).await,
+        Err(e) => {
+            log::error!("Reject {}: {}", file, e);
+            let (sub_dir, name) = split_path(file);
+            adder::move_to_failed(f, &name, &sub_dir, &e.to_string()).await?;
+            Ok(0)
         }
     }
-    Ok(res)
 }
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 1)]
@@ -0,0 +1,336 @@
+use ulid::Ulid;
+
+use super::file::{make_name, sub_folder};
+use crate::audio::probe::AudioInfo;
+use crate::data::{api::ASRMessage, meta};
+use crate::{
+    Storage, DIR_FAILED, DIR_INCOMING, DIR_PROCESSED, DIR_WORKING, FOLDER_CONFIG, INFO_EXTENSION,
+};
+
+/// Recognizer set in the nearest `.config` of `incoming/<sub_dir>` or its parents up to
+/// `incoming/.config`
+pub async fn folder_model(f: &(dyn Storage + Send + Sync), sub_dir: &str) -> Option<String> {
+    let mut dir = sub_dir;
+    loop {
+        match f
+            .read_txt(FOLDER_CONFIG, &sub_folder(DIR_INCOMING, dir))
+            .await
+        {
+            Ok(txt) => {
+                if let Some(model) = meta::model(&meta::parse(&txt)) {
+                    return Some(model);
+                }
+            }
+            Err(e) => log::debug!("No folder config in {:?}: {}", dir, e),
+        }
+        if dir.is_empty() {
+            return None;
+        }
+        dir = dir.rsplit_once('/').map_or("", |(parent, _)| parent);
+    }
+}
+
+/// Moves the audio and its meta file from `incoming/<sub_dir>` to `working/<sub_dir>`.
+/// Returns the new name, it differs if `working` already has such file
+pub async fn move_to_working(
+    f: &(dyn Storage + Send + Sync),
+    file: &str,
+    sub_dir: &str,
+) -> anyhow::Result<String> {
+    let (from, to) = (
+        sub_folder(DIR_INCOMING, sub_dir),
+        sub_folder(DIR_WORKING, sub_dir),
+    );
+    let new_f_name = f.non_existing_name(file, &to).await?;
+    move_with_meta(f, file, &new_f_name, &from, &to).await?;
+    Ok(new_f_name)
+}
+
+/// Moves the rejected file with its meta from `incoming/<sub_dir>` to `failed/<sub_dir>`,
+/// the reason goes to the `.err` file
+pub async fn move_to_failed(
+    f: &(dyn Storage + Send + Sync),
+    file: &str,
+    sub_dir: &str,
+    err: &str,
+) -> anyhow::Result<()> {
+    let (from, to) = (
+        sub_folder(DIR_INCOMING, sub_dir),
+        sub_folder(DIR_FAILED, sub_dir),
+    );
+    let new_f_name = f.non_existing_name(file, &to).await?;
+    f.save_txt(&make_name(&new_f_name, ".err"), &to, err)
+        .await?;
+    move_with_meta(f, file, &new_f_name, &from, &to).await?;
+    Ok(())
+}
+
+/// Moves the file not worth transcribing with its meta from `working/<sub_dir>` to
+/// `processed/<sub_dir>`, the reason goes to the `.skip` file. Returns the new name
+pub async fn move_to_skipped(
+    f: &(dyn Storage + Send + Sync),
+    file: &str,
+    sub_dir: &str,
+    reason: &str,
+) -> anyhow::Result<String> {
+    let (from, to) = (
+        sub_folder(DIR_WORKING, sub_dir),
+        sub_folder(DIR_PROCESSED, sub_dir),
+    );
+    let new_f_name = f.non_existing_name(file, &to).await?;
+    f.save_txt(&make_name(&new_f_name, ".skip"), &to, reason)
+        .await?;
+    move_with_meta(f, file, &new_f_name, &from, &to).await?;
+    Ok(new_f_name)
+}
+
+/// Moves the failed audio with its meta from `failed/<sub_dir>` back to `working/<sub_dir>` as
+/// `file` and drops its `.err`, so the job may be transcribed again
+pub async fn move_back_to_working(
+    f: &(dyn Storage + Send + Sync),
+    failed_name: &str,
+    file: &str,
+    sub_dir: &str,
+) -> anyhow::Result<()> {
+    let (from, to) = (
+        sub_folder(DIR_FAILED, sub_dir),
+        sub_folder(DIR_WORKING, sub_dir),
+    );
+    move_with_meta(f, failed_name, file, &from, &to).await?;
+    // don't fail here, the audio is already back
+    if let Err(e) = f.delete(&make_name(failed_name, ".err"), &from).await {
+        log::warn!("can't delete the error of {}: {}", failed_name, e);
+    }
+    Ok(())
+}
+
+/// Moves the audio with its meta, the audio goes last: once it is in `to`, the files written
+/// there before it are complete. A retry after a crash finds the audio still in `from`
+pub async fn move_with_meta(
+    f: &(dyn Storage + Send + Sync),
+    file: &str,
+    new_f_name: &str,
+    from: &str,
+    to: &str,
+) -> anyhow::Result<()> {
+    if let Err(e) = f
+        .move_to(
+            &make_name(file, INFO_EXTENSION),
+            &make_name(new_f_name, INFO_EXTENSION),
+            from,
+            to,
+        )
+        .await
+    {
+        log::info!("No info file?: {}", e);
+    }
+    f.move_to(file, new_f_name, from, to).await
+}
+
+/// Prepares a new job for the file in `working/<sub_dir>`, the values from the file's meta
+/// take precedence over `model`
+pub async fn make_message(
+    f: &(dyn Storage + Send + Sync),
+    file: &str,
+    sub_dir: &str,
+    base_dir: &str,
+    model: Option<&str>,
+    audio: Option<AudioInfo>,
+) -> ASRMessage {
+    let info = match f
+        .read_txt(
+            &make_name(file, INFO_EXTENSION),
+            &sub_folder(DIR_WORKING, sub_dir),
+        )
+        .await
+    {
+        Ok(txt) => meta::parse(&txt),
+        Err(e) => {
+            log::info!("No info file?: {}", e);
+            Default::default()
+        }
+    };
+    let speakers = meta::speakers(&info);
+    let model = meta::model(&info).or(model.map(|v| v.to_string()));
+    let office = meta::office(&info);
+    let params = meta::asr_params(&info);
+    log::info!("Speakers     : {:?}", speakers);
+    log::info!("Model        : {:?}", model);
+    ASRMessage {
+        id: Ulid::new().to_string(),
+        file: file.to_string(),
+        base_dir: base_dir.to_string(),
+        sub_dir: sub_dir.to_string(),
+        speakers,
+        model,
+        office,
+        params,
+        audio,
+        chunk: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::filer::file::Filer;
+
+    #[tokio::test]
+    async fn test_move_to_working() {
+        let dir = tempfile::tempdir().unwrap();
+        let incoming = dir.path().join(DIR_INCOMING);
+        let working = dir.path().join(DIR_WORKING);
+        fs::create_dir_all(&incoming).unwrap();
+        fs::create_dir_all(&working).unwrap();
+        fs::write(incoming.join("a.wav"), "audio").unwrap();
+        fs::write(incoming.join("a.meta"), "meta").unwrap();
+        fs::write(working.join("a.wav"), "other").unwrap();
+        let f = Filer::new(dir.path().to_str().unwrap());
+
+        assert_eq!("a.1.wav", move_to_working(&f, "a.wav", "").await.unwrap());
+        assert!(working.join("a.1.wav").exists());
+        assert!(working.join("a.1.meta").exists());
+        assert!(!incoming.join("a.wav").exists());
+        assert!(move_to_working(&f, "a.wav", "").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_move_to_working_sub_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let incoming = dir.path().join(DIR_INCOMING).join("team/a");
+        fs::create_dir_all(&incoming).unwrap();
+        fs::write(incoming.join("a.wav"), "audio").unwrap();
+        fs::write(dir.path().join(DIR_INCOMING).join("a.wav"), "top").unwrap();
+        let f = Filer::new(dir.path().to_str().unwrap());
+
+        assert_eq!(
+            "a.wav",
+            move_to_working(&f, "a.wav", "team/a").await.unwrap()
+        );
+        let working = dir.path().join(DIR_WORKING);
+        assert_eq!(
+            "audio",
+            fs::read_to_string(working.join("team/a/a.wav")).unwrap()
+        );
+        assert!(!working.join("a.wav").exists());
+    }
+
+    #[tokio::test]
+    async fn test_move_to_failed() {
+        let dir = tempfile::tempdir().unwrap();
+        let incoming = dir.path().join(DIR_INCOMING).join("team");
+        fs::create_dir_all(&incoming).unwrap();
+        fs::write(incoming.join("a.wav"), "olia").unwrap();
+        fs::write(incoming.join("a.meta"), "meta").unwrap();
+        let f = Filer::new(dir.path().to_str().unwrap());
+
+        move_to_failed(&f, "a.wav", "team", "not audio")
+            .await
+            .unwrap();
+        let failed = dir.path().join(DIR_FAILED).join("team");
+        assert!(failed.join("a.wav").exists());
+        assert!(failed.join("a.meta").exists());
+        assert_eq!(
+            "not audio",
+            fs::read_to_string(failed.join("a.err")).unwrap()
+        );
+        assert!(!incoming.join("a.wav").exists());
+    }
+
+    #[tokio::test]
+    async fn test_move_to_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let working = dir.path().join(DIR_WORKING);
+        let processed = dir.path().join(DIR_PROCESSED);
+        fs::create_dir_all(&working).unwrap();
+        fs::create_dir_all(&processed).unwrap();
+        fs::write(working.join("a.wav"), "olia").unwrap();
+        fs::write(working.join("a.meta"), "meta").unwrap();
+        fs::write(processed.join("a.wav"), "old").unwrap();
+        let f = Filer::new(dir.path().to_str().unwrap());
+
+        assert_eq!(
+            "a.1.wav",
+            move_to_skipped(&f, "a.wav", "", "silence").await.unwrap()
+        );
+        assert_eq!(
+            "olia",
+            fs::read_to_string(processed.join("a.1.wav")).unwrap()
+        );
+        assert!(processed.join("a.1.meta").exists());
+        assert_eq!(
+            "silence",
+            fs::read_to_string(processed.join("a.1.skip")).unwrap()
+        );
+        assert!(!working.join("a.wav").exists());
+    }
+
+    #[tokio::test]
+    async fn test_make_message() {
+        let dir = tempfile::tempdir().unwrap();
+        let working = dir.path().join(DIR_WORKING);
+        fs::create_dir_all(&working).unwrap();
+        fs::write(
+            working.join("a.meta"),
+            "File     : a.wav\nOffice   : Vilnius\nSpeakers : 2\nasr_skip : true\n",
+        )
+        .unwrap();
+        let f = Filer::new(dir.path().to_str().unwrap());
+
+        let actual = make_message(&f, "a.wav", "", "/data", Some("big"), None).await;
+        assert_eq!(26, actual.id.len());
+        assert_eq!("a.wav", actual.file);
+        assert_eq!("/data", actual.base_dir);
+        assert_eq!(Some(2), actual.speakers);
+        assert_eq!(Some("big".to_string()), actual.model);
+        assert_eq!(Some("Vilnius".to_string()), actual.office);
+        assert_eq!(Some(&"true".to_string()), actual.params.get("skip"));
+
+        let actual = make_message(&f, "b.wav", "", "/data", None, None).await;
+        assert_eq!(None, actual.speakers);
+
+        fs::create_dir_all(working.join("team")).unwrap();
+        fs::write(working.join("team/c.meta"), "Speakers : 3\n").unwrap();
+        let actual = make_message(&f, "c.wav", "team", "/data", None, None).await;
+        assert_eq!("team", actual.sub_dir);
+        assert_eq!(Some(3), actual.speakers);
+        assert_eq!(None, actual.model);
+    }
+
+    #[tokio::test]
+    async fn test_folder_model() {
+        let dir = tempfile::tempdir().unwrap();
+        let f = Filer::new(dir.path().to_str().unwrap());
+        assert_eq!(None, folder_model(&f, "").await);
+        let incoming = dir.path().join(DIR_INCOMING);
+        fs::create_dir_all(&incoming).unwrap();
+        fs::write(incoming.join(FOLDER_CONFIG), "Model : small\n").unwrap();
+        assert_eq!(Some("small".to_string()), folder_model(&f, "").await);
+    }
+
+    #[tokio::test]
+    async fn test_folder_model_sub_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let f = Filer::new(dir.path().to_str().unwrap());
+        let incoming = dir.path().join(DIR_INCOMING);
+        for (sub, model) in [("team", "big"), ("court", "small")] {
+            fs::create_dir_all(incoming.join(sub)).unwrap();
+            fs::write(
+                incoming.join(sub).join(FOLDER_CONFIG),
+                format!("Model : {model}\n"),
+            )
+            .unwrap();
+        }
+        fs::create_dir_all(incoming.join("team/a")).unwrap();
+        fs::create_dir_all(incoming.join("other")).unwrap();
+        assert_eq!(Some("big".to_string()), folder_model(&f, "team").await);
+        assert_eq!(Some("small".to_string()), folder_model(&f, "court").await);
+        assert_eq!(Some("big".to_string()), folder_model(&f, "team/a").await);
+        assert_eq!(None, folder_model(&f, "other").await);
+        fs::write(incoming.join(FOLDER_CONFIG), "Model : root\n").unwrap();
+        assert_eq!(Some("root".to_string()), folder_model(&f, "other").await);
+        assert_eq!(Some("big".to_string()), folder_model(&f, "team").await);
+    }
+}
@@ -1,20 +1,35 @@
 use std::{
+    collections::HashMap,
     fs,
     path::{Path, PathBuf},
 };
 
-use axum::{body::Bytes, BoxError};
-use futures::Stream;
-use futures::TryStreamExt;
+use async_trait::async_trait;
+use scopeguard::{guard, ScopeGuard};
 use tokio::{
     fs::File,
-    io::{self, BufWriter},
+    io::{self, AsyncWriteExt, BufWriter},
 };
-use tokio_util::io::StreamReader;
+use tokio_util::io::{ReaderStream, StreamReader};
+use ulid::Ulid;
+
+use unicode_normalization::UnicodeNormalization;
+
+use super::storage::LocalFile;
+use crate::{ByteStream, Storage, DIR_CHUNKS, DIR_WORKING};
+
+/// Max file name length in bytes of the most file systems
+const MAX_NAME_LEN: usize = 255;
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
 
 #[derive(Clone)]
 pub struct Filer {
     base_dir: String,
+    /// Top level folders kept outside of the base dir, e.g. `processed` on the archive mount
+    roots: HashMap<String, PathBuf>,
 }
 
 impl Filer {
@@ -22,130 +37,421 @@ impl Filer {
         log::info!("Creating new Filer with base dir: {}", base_dir);
         Self {
             base_dir: base_dir.to_string(),
+            roots: HashMap::new(),
+        }
+    }
+
+    /// Keeps the top level `folder`, e.g. `incoming`, in `dir` instead of the base dir.
+    /// The moves between the file systems fall back to copy and delete
+    pub fn with_dir(mut self, folder: &str, dir: &str) -> Self {
+        log::info!("{} dir: {}", folder, dir);
+        self.roots.insert(folder.to_string(), PathBuf::from(dir));
+        self
+    }
+
+    /// Local dir of the checked folder, e.g. `processed/team`
+    pub fn dir(&self, folder: &str) -> anyhow::Result<PathBuf> {
+        validate_folder(folder)?;
+        let (top, rest) = folder.split_once('/').unwrap_or((folder, ""));
+        let mut res = match self.roots.get(top) {
+            Some(root) => root.clone(),
+            None => Path::new(&self.base_dir).join(top),
+        };
+        if !rest.is_empty() {
+            res.push(rest);
         }
+        Ok(res)
     }
 
-    pub fn save_txt(&self, f_name: &str, folder: &str, txt: &str) -> anyhow::Result<()> {
+    /// Joins the checked folder and file name onto the folder's dir
+    fn path(&self, f_name: &str, folder: &str) -> anyhow::Result<PathBuf> {
+        validate_name(f_name)?;
+        Ok(self.dir(folder)?.join(f_name))
+    }
+}
+
+#[async_trait]
+impl Storage for Filer {
+    async fn save_txt(&self, f_name: &str, folder: &str, txt: &str) -> anyhow::Result<()> {
         log::info!("saving file: {}", f_name);
-        let mut dest_path = PathBuf::from(self.base_dir.as_str());
-        dest_path.extend(&[folder, f_name]);
-        let f_new = dest_path
-            .to_str()
-            .ok_or("Failed to convert path to string")
-            .map_err(anyhow::Error::msg)?;
-        self.try_create_folder(&dest_path)?;
-        fs::write(f_new, txt)
-            .map_err(|err| format!("Can't write file: {}\n{}", f_new, err))
-            .map_err(anyhow::Error::msg)?;
-        log::info!("saved: {}", f_new);
+        let dest_path = self.path(f_name, folder)?;
+        let (dest, txt) = (dest_path.clone(), txt.to_string());
+        blocking(move || {
+            create_parent(&dest)?;
+            write_atomic(&dest, |temp| {
+                use std::io::Write;
+                let mut file = fs::File::create(temp)?;
+                file.write_all(txt.as_bytes())?;
+                file.sync_all()
+            })
+            .map_err(|err| anyhow::anyhow!("Can't write file: {}\n{}", dest.display(), err))
+        })
+        .await?;
+        log::info!("saved: {}", dest_path.display());
         Ok(())
     }
 
-    pub async fn save_stream<S, E>(
+    async fn save_file(&self, f_name: &str, folder: &str, src: &Path) -> anyhow::Result<()> {
+        let dest_path = self.path(f_name, folder)?;
+        let (dest, src) = (dest_path.clone(), src.to_path_buf());
+        blocking(move || {
+            create_parent(&dest)?;
+            write_atomic(&dest, |temp| copy_synced(&src, temp)).map_err(|err| {
+                anyhow::anyhow!(
+                    "Can't copy {} to {}: {}",
+                    src.display(),
+                    dest.display(),
+                    err
+                )
+            })
+        })
+        .await?;
+        log::info!("saved: {}", dest_path.display());
+        Ok(())
+    }
+
+    async fn read_txt(&self, f_name: &str, folder: &str) -> anyhow::Result<String> {
+        let source_path = self.path(f_name, folder)?;
+        tokio::fs::read_to_string(&source_path)
+            .await
+            .map_err(|err| anyhow::anyhow!("Can't read file: {}\n{}", source_path.display(), err))
+    }
+
+    async fn read_head(&self, f_name: &str, folder: &str, size: usize) -> anyhow::Result<Vec<u8>> {
+        use tokio::io::AsyncReadExt;
+        let source_path = self.path(f_name, folder)?;
+        let mut res = Vec::with_capacity(size);
+        let read = async {
+            File::open(&source_path)
+                .await?
+                .take(size as u64)
+                .read_to_end(&mut res)
+                .await
+        };
+        read.await.map_err(|err| {
+            anyhow::anyhow!("Can't read file: {}\n{}", source_path.display(), err)
+        })?;
+        Ok(res)
+    }
+
+    async fn read_stream(
         &self,
         f_name: &str,
         folder: &str,
-        stream: S,
-    ) -> anyhow::Result<()>
-    where
-        S: Stream<Item = Result<Bytes, E>>,
-        E: Into<BoxError>,
-    {
+    ) -> anyhow::Result<Option<ByteStream<'static>>> {
+        let source_path = self.path(f_name, folder)?;
+        match File::open(&source_path).await {
+            Ok(file) => Ok(Some(Box::pin(ReaderStream::new(file)))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(anyhow::anyhow!(
+                "Can't read file: {}\n{}",
+                source_path.display(),
+                err
+            )),
+        }
+    }
+
+    async fn local_file(&self, f_name: &str, folder: &str) -> anyhow::Result<LocalFile> {
+        let source_path = self.path(f_name, folder)?;
+        if !tokio::fs::try_exists(&source_path).await? {
+            return Err(anyhow::anyhow!(
+                "File {} does not exist",
+                source_path.display()
+            ));
+        }
+        Ok(LocalFile::new(source_path))
+    }
+
+    async fn exists(&self, f_name: &str, folder: &str) -> anyhow::Result<bool> {
+        Ok(tokio::fs::try_exists(self.path(f_name, folder)?).await?)
+    }
+
+    async fn save_stream(
+        &self,
+        f_name: &str,
+        folder: &str,
+        stream: ByteStream<'_>,
+    ) -> anyhow::Result<()> {
         log::info!("saving file: {}", f_name);
-        let mut dest_path = PathBuf::from(self.base_dir.as_str());
-        dest_path.extend(&[folder, f_name]);
+        let dest_path = self.path(f_name, folder)?;
         let f_new = dest_path
             .to_str()
             .ok_or("Failed to convert path to string")
             .map_err(anyhow::Error::msg)?;
-        self.try_create_folder(&dest_path)?;
+        let dest = dest_path.clone();
+        blocking(move || create_parent(&dest)).await?;
 
-        let body_with_io_error = stream.map_err(|err| io::Error::new(io::ErrorKind::Other, err));
-        let body_reader = StreamReader::new(body_with_io_error);
-
-        let mut file = BufWriter::new(File::create(&dest_path).await?);
-        futures::pin_mut!(body_reader);
+        let temp = temp_path(&dest_path);
+        let temp = guard(temp, |temp| remove_temp(&temp));
+        let mut body_reader = StreamReader::new(stream);
+        let mut file = BufWriter::new(File::create(temp.as_path()).await?);
 
         // Copy the body into the file.
         tokio::io::copy(&mut body_reader, &mut file).await?;
+        file.flush().await?;
+        file.get_ref().sync_all().await?;
+        drop(file);
+        let (temp, dest) = (ScopeGuard::into_inner(temp), dest_path.clone());
+        blocking(move || Ok(commit(&temp, &dest)?)).await?;
         log::info!("saved: {}", f_new);
         Ok(())
     }
 
-    pub fn non_existing_name(&self, f_name: &str, folder: &str) -> anyhow::Result<String> {
-        let mut i = 0;
-        loop {
-            let new_name = make_new_name(f_name, i);
-            let mut dest_path = PathBuf::from(self.base_dir.as_str());
-            dest_path.extend(&[folder, &new_name]);
-            if !dest_path.exists() {
-                return Ok(new_name);
-            }
-            i += 1;
-        }
-    }
-
-    fn try_create_folder(&self, dest_path: &Path) -> anyhow::Result<()> {
-        if let Some(dest_dir) = dest_path.parent() {
-            if !dest_dir.exists() {
-                log::info!("creating: {:?}", dest_dir);
-                fs::create_dir_all(dest_dir).map_err(|err| {
-                    anyhow::anyhow!("Failed to create directory {}: {}", dest_dir.display(), err)
-                })?;
-            }
-        }
-        Ok(())
-    }
-
-    pub fn move_to(
+    async fn move_to(
         &self,
         f_name: &str,
         to_name: &str,
         dir_from: &str,
         dir_to: &str,
     ) -> anyhow::Result<()> {
-        let mut source_path = PathBuf::from(self.base_dir.as_str());
-        source_path.extend(&[dir_from, f_name]);
+        let source_path = self.path(f_name, dir_from)?;
         let f = source_path
             .to_str()
             .ok_or("Failed to convert path to string")
-            .map_err(anyhow::Error::msg)?;
+            .map_err(anyhow::Error::msg)?
+            .to_string();
         log::info!("Adding file: {}", f);
-        if !source_path.exists() {
+        if !tokio::fs::try_exists(&source_path).await? {
             return Err(anyhow::anyhow!("File {f} does not exist"));
         }
-        let mut dest_path = PathBuf::from(self.base_dir.as_str());
-        dest_path.extend(&[dir_to, to_name]);
+        let dest_path = self.path(to_name, dir_to)?;
         let f_new = dest_path
             .to_str()
             .ok_or("Failed to convert path to string")
             .map_err(anyhow::Error::msg)?;
 
-        self.try_create_folder(&dest_path)?;
-        if dest_path.exists() {
-            fs::remove_file(&dest_path).map_err(|err| {
-                anyhow::anyhow!("Failed to remove existing file {}: {}", f_new, err)
-            })?;
-        }
-        fs::rename(&source_path, &dest_path)
-            .map_err(|err| format!("Can't move file: {}\n{}", f, err))
-            .map_err(anyhow::Error::msg)?;
+        let (src, dest) = (source_path.clone(), dest_path.clone());
+        blocking(move || {
+            create_parent(&dest)?;
+            move_file(&src, &dest)
+                .map_err(|err| anyhow::anyhow!("Can't move file: {}\n{}", src.display(), err))
+        })
+        .await?;
         log::info!("moved: {} -> {}", f, f_new);
         Ok(())
     }
 
-    pub fn delete(&self, f_name: &str, dir_incoming: &str) -> anyhow::Result<()> {
-        let mut source_path = PathBuf::from(self.base_dir.as_str());
-        source_path.extend(&[dir_incoming, f_name]);
+    async fn delete(&self, f_name: &str, folder: &str) -> anyhow::Result<()> {
+        let source_path = self.path(f_name, folder)?;
         let f = source_path
             .to_str()
             .ok_or("Failed to convert path to string")
             .map_err(anyhow::Error::msg)?;
         tracing::info!(file = f, "delete");
-        fs::remove_file(source_path).map_err(anyhow::Error::msg)
+        tokio::fs::remove_file(&source_path)
+            .await
+            .map_err(anyhow::Error::msg)
+    }
+
+    async fn delete_dir(&self, folder: &str) -> anyhow::Result<()> {
+        if folder.is_empty() {
+            return Err(anyhow::anyhow!("no folder to delete"));
+        }
+        let source_path = self.dir(folder)?;
+        log::info!("delete dir: {}", source_path.display());
+        tokio::fs::remove_dir_all(&source_path)
+            .await
+            .map_err(|err| anyhow::anyhow!("Can't delete {}: {}", source_path.display(), err))
+    }
+}
+
+/// Runs the blocking file system calls and syncs on the blocking thread pool, off the async
+/// workers
+async fn blocking<T, F>(work: F) -> anyhow::Result<T>
+where
+    F: FnOnce() -> anyhow::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(work).await?
+}
+
+fn create_parent(dest_path: &Path) -> anyhow::Result<()> {
+    if let Some(dest_dir) = dest_path.parent() {
+        if !dest_dir.exists() {
+            log::info!("creating: {:?}", dest_dir);
+            fs::create_dir_all(dest_dir).map_err(|err| {
+                anyhow::anyhow!("Failed to create directory {}: {}", dest_dir.display(), err)
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// `errno` of the rename across file systems on Linux and macOS
+const EXDEV: i32 = 18;
+
+/// Temp file next to `dest`, hidden and without the audio extension so the scanners skip it
+fn temp_path(dest: &Path) -> PathBuf {
+    dest.with_file_name(format!(".{}.tmp", Ulid::new()))
+}
+
+fn remove_temp(temp: &Path) {
+    if let Err(err) = fs::remove_file(temp) {
+        if err.kind() != io::ErrorKind::NotFound {
+            log::warn!("can't remove {}: {}", temp.display(), err);
+        }
+    }
+}
+
+/// Writes the synced temp file with `write` and renames it to `dest`, so readers see either
+/// the old file or the complete new one
+fn write_atomic<F>(dest: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&Path) -> io::Result<()>,
+{
+    let temp = guard(temp_path(dest), |temp| remove_temp(&temp));
+    write(&temp)?;
+    commit(&ScopeGuard::into_inner(temp), dest)
+}
+
+/// Renames the synced temp file to `dest` and syncs the dir to persist the rename
+fn commit(temp: &Path, dest: &Path) -> io::Result<()> {
+    if let Err(err) = fs::rename(temp, dest) {
+        remove_temp(temp);
+        return Err(err);
+    }
+    sync_parent(dest)
+}
+
+fn copy_synced(src: &Path, dest: &Path) -> io::Result<()> {
+    fs::copy(src, dest)?;
+    fs::File::open(dest)?.sync_all()
+}
+
+fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) => fs::File::open(dir)?.sync_all(),
+        None => Ok(()),
+    }
+}
+
+/// Renames `src` over `dest`, copies and deletes it if they are on different file systems.
+/// The existing `dest` stays in place until the new one is complete
+fn move_file(src: &Path, dest: &Path) -> io::Result<()> {
+    match fs::rename(src, dest) {
+        Ok(()) => {
+            sync_parent(dest)?;
+            sync_parent(src)
+        }
+        Err(err) if err.raw_os_error() == Some(EXDEV) => {
+            log::info!("{} is on another file system, copying", dest.display());
+            copy_move(src, dest)
+        }
+        Err(err) => Err(err),
     }
 }
 
+/// The `src` is deleted only when the synced copy is in place
+fn copy_move(src: &Path, dest: &Path) -> io::Result<()> {
+    write_atomic(dest, |temp| copy_synced(src, temp))?;
+    fs::remove_file(src)?;
+    sync_parent(src)
+}
+
+/// Folder of the long audio parts, e.g. `working/.chunks/<job id>`
+pub fn chunk_folder(id: &str) -> String {
+    format!("{}/{}/{}", DIR_WORKING, DIR_CHUNKS, id)
+}
+
+/// Folder with the optional sub dir, e.g. `processed/team`
+pub fn sub_folder(folder: &str, sub_dir: &str) -> String {
+    if sub_dir.is_empty() {
+        folder.to_string()
+    } else {
+        format!("{}/{}", folder, sub_dir)
+    }
+}
+
+/// Splits the relative file path into the sub dir and the file name
+pub fn split_path(f_path: &str) -> (String, String) {
+    match f_path.rsplit_once('/') {
+        Some((dir, name)) => (dir.to_string(), name.to_string()),
+        None => ("".to_string(), f_path.to_string()),
+    }
+}
+
+/// Joins the sub dir and the file name into the relative file path, see `split_path`
+pub fn sub_path(sub_dir: &str, f_name: &str) -> String {
+    if sub_dir.is_empty() {
+        f_name.to_string()
+    } else {
+        format!("{}/{}", sub_dir, f_name)
+    }
+}
+
+/// Checks that the sub dir stays inside its top folder
+pub fn validate_sub_dir(sub_dir: &str) -> anyhow::Result<()> {
+    if sub_dir.is_empty() {
+        return Ok(());
+    }
+    if sub_dir.starts_with('/')
+        || sub_dir.contains('\\')
+        || sub_dir
+            .split('/')
+            .any(|v| v.is_empty() || v == "." || v == ".." || v.starts_with('.'))
+    {
+        return Err(anyhow::anyhow!("wrong sub dir '{}'", sub_dir));
+    }
+    Ok(())
+}
+
+/// Checks that the internal folder, e.g. `working/.chunks/<id>`, stays inside the base dir
+pub fn validate_folder(folder: &str) -> anyhow::Result<()> {
+    if folder.is_empty() {
+        return Ok(());
+    }
+    if folder.starts_with('/')
+        || folder.contains('\\')
+        || folder.chars().any(char::is_control)
+        || folder
+            .split('/')
+            .any(|v| v.is_empty() || v == "." || v == "..")
+    {
+        return Err(anyhow::anyhow!("wrong folder '{}'", folder.escape_debug()));
+    }
+    Ok(())
+}
+
+/// Checks that the name is a single plain file name
+pub fn validate_name(f_name: &str) -> anyhow::Result<()> {
+    if f_name.is_empty()
+        || f_name == "."
+        || f_name == ".."
+        || f_name.len() > MAX_NAME_LEN
+        || f_name.contains(['/', '\\'])
+        || f_name.chars().any(char::is_control)
+        || is_reserved(f_name)
+    {
+        return Err(anyhow::anyhow!(
+            "wrong file name '{}'",
+            f_name.escape_debug()
+        ));
+    }
+    Ok(())
+}
+
+/// Makes the safe file name from the client supplied one: NFC normalized and space trimmed,
+/// hidden files and anything that is not a plain file name are rejected
+pub fn sanitize_name(f_name: &str) -> anyhow::Result<String> {
+    let res: String = f_name.nfc().collect();
+    // only spaces, control chars are rejected below
+    let res = res.trim_matches(' ');
+    if res.starts_with('.') {
+        return Err(anyhow::anyhow!(
+            "wrong file name '{}'",
+            f_name.escape_debug()
+        ));
+    }
+    validate_name(res)?;
+    Ok(res.to_string())
+}
+
+/// Windows device names, the extension does not matter: `nul.wav` is still `NUL`
+fn is_reserved(f_name: &str) -> bool {
+    let stem = f_name.split('.').next().unwrap_or_default().trim_end();
+    RESERVED_NAMES.iter().any(|v| v.eq_ignore_ascii_case(stem))
+}
+
 pub fn make_name(f_name: &str, ext: &str) -> String {
     let path = Path::new(f_name);
     let mut new_path = PathBuf::from(path);
@@ -176,6 +482,7 @@ pub fn make_new_name(f_name: &str, num: i32) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use axum::body::Bytes;
     use test_case::test_case;
 
     #[test_case("document.wav", ".txt", "document.txt"; "change extension")]
@@ -185,6 +492,231 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test_case("", "processed", "processed"; "empty")]
+    #[test_case("a/b", "failed", "failed/a/b"; "nested")]
+    fn test_sub_folder(sub_dir: &str, folder: &str, expected: &str) {
+        assert_eq!(expected, sub_folder(folder, sub_dir));
+    }
+
+    #[test_case("a.wav", "", "a.wav"; "top")]
+    #[test_case("team/a.wav", "team", "a.wav"; "sub")]
+    #[test_case("a/b/c.wav", "a/b", "c.wav"; "nested")]
+    fn test_split_path(f_path: &str, dir: &str, name: &str) {
+        assert_eq!((dir.to_string(), name.to_string()), split_path(f_path));
+        assert_eq!(f_path, sub_path(dir, name));
+    }
+
+    #[test_case("", true; "empty")]
+    #[test_case("team", true; "plain")]
+    #[test_case("a/b c", true; "nested")]
+    #[test_case("/etc", false; "absolute")]
+    #[test_case("a/../..", false; "parent")]
+    #[test_case("a//b", false; "empty part")]
+    #[test_case(".git", false; "hidden")]
+    #[test_case("a\\b", false; "backslash")]
+    fn test_validate_sub_dir(sub_dir: &str, expected: bool) {
+        assert_eq!(expected, validate_sub_dir(sub_dir).is_ok());
+    }
+
+    #[test_case("a.wav", true; "plain")]
+    #[test_case(".a.lat.txt", true; "hidden")]
+    #[test_case("Kėdainių posėdis.mp3", true; "lithuanian")]
+    #[test_case("", false; "empty")]
+    #[test_case("..", false; "parent")]
+    #[test_case("../a.wav", false; "parent path")]
+    #[test_case("/etc/passwd", false; "absolute")]
+    #[test_case("a\\b.wav", false; "backslash")]
+    #[test_case("a\nb.wav", false; "new line")]
+    #[test_case("a\0.wav", false; "nul char")]
+    #[test_case("nul.wav", false; "reserved")]
+    #[test_case("Com1", false; "reserved case")]
+    #[test_case("console.wav", true; "reserved prefix")]
+    fn test_validate_name(f_name: &str, expected: bool) {
+        assert_eq!(expected, validate_name(f_name).is_ok());
+    }
+
+    #[test]
+    fn test_validate_name_long() {
+        assert!(validate_name(&"ą".repeat(127)).is_ok());
+        assert!(validate_name(&"ą".repeat(128)).is_err());
+    }
+
+    #[test_case("working", true; "top")]
+    #[test_case("working/.chunks/01J", true; "chunks")]
+    #[test_case("", true; "empty")]
+    #[test_case("/tmp", false; "absolute")]
+    #[test_case("working/../..", false; "parent")]
+    #[test_case("working/", false; "trailing slash")]
+    #[test_case("work\ting", false; "control")]
+    fn test_validate_folder(folder: &str, expected: bool) {
+        assert_eq!(expected, validate_folder(folder).is_ok());
+    }
+
+    #[test_case(" a.wav ", "a.wav"; "trim")]
+    #[test_case("Ke\u{307}dainiai.wav", "K\u{117}dainiai.wav"; "nfc")]
+    #[test_case("žąsis ąžuolas.m4a", "žąsis ąžuolas.m4a"; "already nfc")]
+    fn test_sanitize_name(f_name: &str, expected: &str) {
+        assert_eq!(expected, sanitize_name(f_name).unwrap());
+    }
+
+    #[test_case("../../etc/cron.d/x.wav"; "traversal")]
+    #[test_case("..\\..\\boot.ini.wav"; "windows traversal")]
+    #[test_case("C:\\Users\\a.wav"; "windows path")]
+    #[test_case("/root/a.wav"; "absolute")]
+    #[test_case("a.wav\r\nX-Header: 1"; "header injection")]
+    #[test_case(".config"; "hidden")]
+    #[test_case("CON.wav"; "reserved")]
+    #[test_case("   "; "blank")]
+    fn test_sanitize_name_fail(f_name: &str) {
+        assert!(sanitize_name(f_name).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_filer_rejects_hostile_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base");
+        let f = Filer::new(base.to_str().unwrap());
+        assert!(f.save_txt("../x.txt", "incoming", "x").await.is_err());
+        assert!(f.save_txt("x.txt", "../incoming", "x").await.is_err());
+        assert!(f.save_txt("x.txt", "/incoming", "x").await.is_err());
+        assert!(f.read_txt("../../x.txt", "incoming").await.is_err());
+        assert!(f
+            .move_to("x.txt", "../x.txt", "incoming", "working")
+            .await
+            .is_err());
+        assert!(f.delete("x.txt", "incoming/..").await.is_err());
+        assert!(f.delete_dir("").await.is_err());
+        assert!(f.delete_dir("..").await.is_err());
+        assert!(!dir.path().join("x.txt").exists());
+        assert!(!dir.path().join("incoming").exists());
+
+        f.save_txt("x.txt", "working/.chunks/01J", "x")
+            .await
+            .unwrap();
+        assert_eq!(
+            "x",
+            f.read_txt("x.txt", "working/.chunks/01J").await.unwrap()
+        );
+        f.delete_dir("working/.chunks/01J").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_save_stream_rejects_hostile_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let f = Filer::new(dir.path().join("base").to_str().unwrap());
+        let stream = futures::stream::iter(vec![Ok(Bytes::from_static(b"x"))]);
+        assert!(f
+            .save_stream("../../x.wav", "incoming", Box::pin(stream))
+            .await
+            .is_err());
+        assert!(!dir.path().join("x.wav").exists());
+    }
+
+    #[tokio::test]
+    async fn test_filer() {
+        let dir = tempfile::tempdir().unwrap();
+        let f = Filer::new(dir.path().to_str().unwrap());
+        let stream = futures::stream::iter(vec![
+            Ok(Bytes::from_static(b"au")),
+            Ok(Bytes::from_static(b"dio")),
+        ]);
+        f.save_stream("a.wav", "incoming", Box::pin(stream))
+            .await
+            .unwrap();
+        assert_eq!(
+            b"aud".to_vec(),
+            f.read_head("a.wav", "incoming", 3).await.unwrap()
+        );
+        assert!(f.exists("a.wav", "incoming").await.unwrap());
+        assert_eq!(
+            "a.1.wav",
+            f.non_existing_name("a.wav", "incoming").await.unwrap()
+        );
+        let local = f.local_file("a.wav", "incoming").await.unwrap();
+        f.save_file("b.wav", "working", local.path()).await.unwrap();
+        drop(local);
+        assert!(dir.path().join("incoming/a.wav").exists());
+        assert_eq!(
+            "audio",
+            fs::read_to_string(dir.path().join("working/b.wav")).unwrap()
+        );
+        assert!(f.read_stream("c.wav", "working").await.unwrap().is_none());
+        assert!(f.local_file("c.wav", "working").await.is_err());
+    }
+
+    fn dir_names(dir: &Path) -> Vec<String> {
+        let mut res: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        res.sort();
+        res
+    }
+
+    #[tokio::test]
+    async fn test_save_replaces_without_temp_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let f = Filer::new(dir.path().to_str().unwrap());
+        f.save_txt("a.txt", "processed", "old").await.unwrap();
+        f.save_txt("a.txt", "processed", "new").await.unwrap();
+        let src = dir.path().join("src.wav");
+        fs::write(&src, "audio").unwrap();
+        f.save_file("a.wav", "processed", &src).await.unwrap();
+        assert_eq!("new", f.read_txt("a.txt", "processed").await.unwrap());
+        assert_eq!(
+            vec!["a.txt", "a.wav"],
+            dir_names(&dir.path().join("processed"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_stream_failed_keeps_old() {
+        let dir = tempfile::tempdir().unwrap();
+        let f = Filer::new(dir.path().to_str().unwrap());
+        f.save_txt("a.wav", "incoming", "old").await.unwrap();
+        let stream = futures::stream::iter(vec![
+            Ok(Bytes::from_static(b"au")),
+            Err(std::io::Error::other("broken")),
+        ]);
+        assert!(f
+            .save_stream("a.wav", "incoming", Box::pin(stream))
+            .await
+            .is_err());
+        assert_eq!("old", f.read_txt("a.wav", "incoming").await.unwrap());
+        assert_eq!(vec!["a.wav"], dir_names(&dir.path().join("incoming")));
+    }
+
+    #[tokio::test]
+    async fn test_move_to_replaces() {
+        let dir = tempfile::tempdir().unwrap();
+        let f = Filer::new(dir.path().to_str().unwrap());
+        f.save_txt("a.wav", "working", "new").await.unwrap();
+        f.save_txt("b.wav", "processed", "old").await.unwrap();
+        f.move_to("a.wav", "b.wav", "working", "processed")
+            .await
+            .unwrap();
+        assert_eq!("new", f.read_txt("b.wav", "processed").await.unwrap());
+        assert!(!f.exists("a.wav", "working").await.unwrap());
+        assert!(f
+            .move_to("a.wav", "b.wav", "working", "processed")
+            .await
+            .is_err());
+        assert_eq!("new", f.read_txt("b.wav", "processed").await.unwrap());
+    }
+
+    #[test]
+    fn test_copy_move() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dest) = (dir.path().join("a.wav"), dir.path().join("b.wav"));
+        fs::write(&src, "new").unwrap();
+        fs::write(&dest, "old").unwrap();
+        copy_move(&src, &dest).unwrap();
+        assert_eq!("new", fs::read_to_string(&dest).unwrap());
+        assert_eq!(vec!["b.wav"], dir_names(dir.path()));
+        assert!(copy_move(&src, &dest).is_err());
+        assert_eq!(vec!["b.wav"], dir_names(dir.path()));
+    }
+
     #[test_case("document.wav", 0, "document.wav"; "same")]
     #[test_case("archive.tar.gz", 0, "archive.tar.gz"; "several extensions")]
     #[test_case("document.wav", 1, "document.1.wav"; "same add")]
@@ -0,0 +1,217 @@
+use std::{collections::HashMap, path::Path, time::Duration};
+
+use chrono::{DateTime, NaiveDateTime, SubsecRound, Utc};
+
+use crate::{audio::format::Formats, model::models::IncomingFile};
+
+/// Audio file in `incoming`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileState {
+    pub name: String,
+    pub size: i64,
+    pub modified: NaiveDateTime,
+}
+
+/// What to do with the files after comparing them to the previous scans
+#[derive(Debug, Default, PartialEq)]
+pub struct Check {
+    /// Unchanged for the stable time, can be queued
+    pub ready: Vec<String>,
+    /// New or changed, to be remembered
+    pub changed: Vec<IncomingFile>,
+    /// Unchanged, but not long enough
+    pub waiting: Vec<String>,
+    /// Remembered, but not in the dir anymore
+    pub gone: Vec<String>,
+}
+
+/// Lists the audio files in the dir and its sub dirs, sorted by name.
+/// The names are relative to `dir`, hidden dirs are skipped
+pub fn list_audio(dir: &Path, formats: &Formats) -> anyhow::Result<Vec<FileState>> {
+    let mut res = Vec::new();
+    list_dir(dir, "", formats, &mut res)?;
+    res.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(res)
+}
+
+fn list_dir(
+    dir: &Path,
+    prefix: &str,
+    formats: &Formats,
+    res: &mut Vec<FileState>,
+) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let Some(name) = path.file_name().and_then(|v| v.to_str()) else {
+            log::warn!("skip non utf-8 name: {}", path.display());
+            continue;
+        };
+        let rel_name = if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if !name.starts_with('.') {
+                list_dir(&path, &rel_name, formats, res)?;
+            }
+            continue;
+        }
+        if !file_type.is_file() || !formats.is_audio(&path) {
+            continue;
+        }
+        let md = entry.metadata()?;
+        res.push(FileState {
+            name: rel_name,
+            size: md.len() as i64,
+            // the DB keeps microseconds
+            modified: DateTime::<Utc>::from(md.modified()?)
+                .naive_utc()
+                .trunc_subsecs(6),
+        });
+    }
+    Ok(())
+}
+
+/// Compares the files with the states from the previous scans.
+/// A file is ready if its size and modification time have not changed for `stable`
+pub fn check(
+    base_dir: &str,
+    files: &[FileState],
+    known: &[IncomingFile],
+    now: NaiveDateTime,
+    stable: Duration,
+) -> Check {
+    let mut known: HashMap<&str, &IncomingFile> =
+        known.iter().map(|v| (v.name.as_str(), v)).collect();
+    let mut res = Check::default();
+    for file in files {
+        match known.remove(file.name.as_str()) {
+            Some(prev) if prev.size == file.size && prev.modified == file.modified => {
+                if (now - prev.seen).to_std().unwrap_or_default() >= stable {
+                    res.ready.push(file.name.clone());
+                } else {
+                    res.waiting.push(file.name.clone());
+                }
+            }
+            _ => res.changed.push(IncomingFile {
+                base_dir: base_dir.to_string(),
+                name: file.name.clone(),
+                size: file.size,
+                modified: file.modified,
+                seen: now,
+            }),
+        }
+    }
+    res.gone = known.into_keys().map(|v| v.to_string()).collect();
+    res.gone.sort();
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn time(s: &str) -> NaiveDateTime {
+        s.parse().unwrap()
+    }
+
+    fn state(name: &str, size: i64, modified: &str) -> FileState {
+        FileState {
+            name: name.to_string(),
+            size,
+            modified: time(modified),
+        }
+    }
+
+    fn known(name: &str, size: i64, modified: &str, seen: &str) -> IncomingFile {
+        IncomingFile {
+            base_dir: "/data".to_string(),
+            name: name.to_string(),
+            size,
+            modified: time(modified),
+            seen: time(seen),
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let now = time("2024-08-12T10:00:30");
+        let files = [
+            state("new.wav", 10, "2024-08-12T10:00:00"),
+            state("ready.wav", 10, "2024-08-12T09:00:00"),
+            state("waiting.wav", 10, "2024-08-12T10:00:00"),
+            state("growing.wav", 20, "2024-08-12T10:00:29"),
+        ];
+        let prev = [
+            known(
+                "ready.wav",
+                10,
+                "2024-08-12T09:00:00",
+                "2024-08-12T10:00:00",
+            ),
+            known(
+                "waiting.wav",
+                10,
+                "2024-08-12T10:00:00",
+                "2024-08-12T10:00:25",
+            ),
+            known(
+                "growing.wav",
+                10,
+                "2024-08-12T10:00:20",
+                "2024-08-12T10:00:20",
+            ),
+            known(
+                "deleted.wav",
+                10,
+                "2024-08-12T10:00:00",
+                "2024-08-12T10:00:00",
+            ),
+        ];
+        let actual = check("/data", &files, &prev, now, Duration::from_secs(10));
+        assert_eq!(vec!["ready.wav".to_string()], actual.ready);
+        assert_eq!(vec!["waiting.wav".to_string()], actual.waiting);
+        assert_eq!(vec!["deleted.wav".to_string()], actual.gone);
+        assert_eq!(
+            vec![
+                known("new.wav", 10, "2024-08-12T10:00:00", "2024-08-12T10:00:30"),
+                known(
+                    "growing.wav",
+                    20,
+                    "2024-08-12T10:00:29",
+                    "2024-08-12T10:00:30"
+                ),
+            ],
+            actual.changed
+        );
+    }
+
+    #[test]
+    fn test_list_audio() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("b.wav"), "audio").unwrap();
+        fs::write(dir.path().join("a.mp3"), "au").unwrap();
+        fs::write(dir.path().join("a.meta"), "meta").unwrap();
+        fs::create_dir(dir.path().join("c.wav")).unwrap();
+        fs::create_dir_all(dir.path().join("team/sub")).unwrap();
+        fs::write(dir.path().join("team/sub/d.wav"), "aud").unwrap();
+        fs::create_dir(dir.path().join(".hidden")).unwrap();
+        fs::write(dir.path().join(".hidden/e.wav"), "aud").unwrap();
+        let actual = list_audio(dir.path(), &Formats::default()).unwrap();
+        assert_eq!(
+            vec![("a.mp3", 2), ("b.wav", 5), ("team/sub/d.wav", 3)],
+            actual
+                .iter()
+                .map(|v| (v.name.as_str(), v.size))
+                .collect::<Vec<_>>()
+        );
+        assert!(actual
+            .iter()
+            .all(|v| v.modified.and_utc().timestamp_subsec_nanos() % 1000 == 0));
+    }
+}
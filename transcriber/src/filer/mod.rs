@@ -1 +1,5 @@
+pub mod adder;
 pub mod file;
+pub mod incoming;
+pub mod s3;
+pub mod storage;
@@ -0,0 +1,379 @@
+use std::{path::Path as FsPath, sync::Arc};
+
+use async_trait::async_trait;
+use futures::{StreamExt, TryStreamExt};
+use object_store::{
+    aws::AmazonS3Builder, buffered::BufWriter, path::Path, ObjectStore, PutPayload,
+};
+use tokio::io::{AsyncRead, AsyncWriteExt};
+use tokio_util::io::StreamReader;
+
+use super::file::{validate_folder, validate_name};
+use super::storage::{LocalFile, S3_SCHEME};
+use crate::{ByteStream, Storage};
+
+/// Files in the S3 compatible object storage (AWS, MinIO), the folders are key prefixes
+pub struct S3Filer {
+    store: Arc<dyn ObjectStore>,
+    root: String,
+}
+
+impl S3Filer {
+    /// Opens `s3://bucket/prefix`, the endpoint and the credentials are taken from the `AWS_*`
+    /// env variables, e.g. `AWS_ENDPOINT=http://minio:9000`, `AWS_ALLOW_HTTP=true`
+    pub fn new(url: &str) -> anyhow::Result<Self> {
+        let (bucket, root) = parse_url(url)?;
+        log::info!(
+            "Creating new S3 Filer, bucket: {}, prefix: {}",
+            bucket,
+            root
+        );
+        let store = AmazonS3Builder::from_env()
+            .with_bucket_name(bucket)
+            .build()
+            .map_err(|err| anyhow::anyhow!("can't init S3 storage {}: {}", url, err))?;
+        Ok(Self::with_store(Arc::new(store), &root))
+    }
+
+    pub fn with_store(store: Arc<dyn ObjectStore>, root: &str) -> Self {
+        Self {
+            store,
+            root: root.to_string(),
+        }
+    }
+
+    fn key(&self, f_name: &str, folder: &str) -> anyhow::Result<Path> {
+        validate_folder(folder)?;
+        validate_name(f_name)?;
+        Ok(Path::from(format!("{}/{}/{}", self.root, folder, f_name)))
+    }
+
+    async fn upload<R>(&self, key: Path, reader: &mut R) -> anyhow::Result<()>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        let mut writer = BufWriter::new(self.store.clone(), key.clone());
+        let res = match tokio::io::copy(reader, &mut writer).await {
+            Ok(_) => writer.shutdown().await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = res {
+            if let Err(err) = writer.abort().await {
+                log::warn!("can't abort upload of {}: {}", key, err);
+            }
+            return Err(anyhow::anyhow!("Can't write file: {}\n{}", key, err));
+        }
+        log::info!("saved: {}", key);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for S3Filer {
+    async fn save_stream(
+        &self,
+        f_name: &str,
+        folder: &str,
+        stream: ByteStream<'_>,
+    ) -> anyhow::Result<()> {
+        log::info!("saving file: {}", f_name);
+        let key = self.key(f_name, folder)?;
+        self.upload(key, &mut StreamReader::new(stream)).await
+    }
+
+    async fn save_txt(&self, f_name: &str, folder: &str, txt: &str) -> anyhow::Result<()> {
+        log::info!("saving file: {}", f_name);
+        let key = self.key(f_name, folder)?;
+        self.store
+            .put(&key, PutPayload::from(txt.to_string()))
+            .await
+            .map_err(|err| anyhow::anyhow!("Can't write file: {}\n{}", key, err))?;
+        log::info!("saved: {}", key);
+        Ok(())
+    }
+
+    async fn save_file(&self, f_name: &str, folder: &str, src: &FsPath) -> anyhow::Result<()> {
+        let key = self.key(f_name, folder)?;
+        let mut file = tokio::fs::File::open(src)
+            .await
+            .map_err(|err| anyhow::anyhow!("Can't read file: {}\n{}", src.display(), err))?;
+        self.upload(key, &mut file).await
+    }
+
+    async fn read_txt(&self, f_name: &str, folder: &str) -> anyhow::Result<String> {
+        let key = self.key(f_name, folder)?;
+        let data = async { self.store.get(&key).await?.bytes().await }
+            .await
+            .map_err(|err| anyhow::anyhow!("Can't read file: {}\n{}", key, err))?;
+        Ok(String::from_utf8(data.to_vec())?)
+    }
+
+    async fn read_head(&self, f_name: &str, folder: &str, size: usize) -> anyhow::Result<Vec<u8>> {
+        let key = self.key(f_name, folder)?;
+        let mut stream = self
+            .store
+            .get(&key)
+            .await
+            .map_err(|err| anyhow::anyhow!("Can't read file: {}\n{}", key, err))?
+            .into_stream();
+        let mut res = Vec::with_capacity(size);
+        while res.len() < size {
+            match stream.next().await {
+                Some(data) => res.extend_from_slice(&data?),
+                None => break,
+            }
+        }
+        res.truncate(size);
+        Ok(res)
+    }
+
+    async fn read_stream(
+        &self,
+        f_name: &str,
+        folder: &str,
+    ) -> anyhow::Result<Option<ByteStream<'static>>> {
+        let key = self.key(f_name, folder)?;
+        match self.store.get(&key).await {
+            Ok(res) => Ok(Some(Box::pin(
+                res.into_stream().map_err(std::io::Error::other),
+            ))),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(err) => Err(anyhow::anyhow!("Can't read file: {}\n{}", key, err)),
+        }
+    }
+
+    async fn local_file(&self, f_name: &str, folder: &str) -> anyhow::Result<LocalFile> {
+        let stream = self
+            .read_stream(f_name, folder)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("File {}/{} does not exist", folder, f_name))?;
+        let dir = std::env::temp_dir().join(format!("transcriber-{}", ulid::Ulid::new()));
+        tokio::fs::create_dir_all(&dir).await?;
+        // removes the dir if the download fails
+        let res = LocalFile::temp(dir.join(f_name), dir);
+        let mut file = tokio::fs::File::create(res.path()).await?;
+        tokio::io::copy(&mut StreamReader::new(stream), &mut file).await?;
+        file.flush().await?;
+        log::info!(
+            "downloaded {}/{} to {}",
+            folder,
+            f_name,
+            res.path().display()
+        );
+        Ok(res)
+    }
+
+    async fn exists(&self, f_name: &str, folder: &str) -> anyhow::Result<bool> {
+        let key = self.key(f_name, folder)?;
+        match self.store.head(&key).await {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(err) => Err(anyhow::anyhow!("Can't check file: {}\n{}", key, err)),
+        }
+    }
+
+    async fn move_to(
+        &self,
+        f_name: &str,
+        to_name: &str,
+        dir_from: &str,
+        dir_to: &str,
+    ) -> anyhow::Result<()> {
+        let from = self.key(f_name, dir_from)?;
+        let to = self.key(to_name, dir_to)?;
+        if !self.exists(f_name, dir_from).await? {
+            return Err(anyhow::anyhow!("File {from} does not exist"));
+        }
+        self.store
+            .rename(&from, &to)
+            .await
+            .map_err(|err| anyhow::anyhow!("Can't move file: {}\n{}", from, err))?;
+        log::info!("moved: {} -> {}", from, to);
+        Ok(())
+    }
+
+    async fn delete(&self, f_name: &str, folder: &str) -> anyhow::Result<()> {
+        let key = self.key(f_name, folder)?;
+        tracing::info!(file = key.as_ref(), "delete");
+        // S3 does not fail on the missing object, the local filer does
+        if !self.exists(f_name, folder).await? {
+            return Err(anyhow::anyhow!("File {key} does not exist"));
+        }
+        Ok(self.store.delete(&key).await?)
+    }
+
+    async fn delete_dir(&self, folder: &str) -> anyhow::Result<()> {
+        if folder.is_empty() {
+            return Err(anyhow::anyhow!("no folder to delete"));
+        }
+        validate_folder(folder)?;
+        let prefix = Path::from(format!("{}/{}", self.root, folder));
+        log::info!("delete dir: {}", prefix);
+        let keys: Vec<Path> = self
+            .store
+            .list(Some(&prefix))
+            .map_ok(|v| v.location)
+            .try_collect()
+            .await?;
+        for key in keys {
+            self.store
+                .delete(&key)
+                .await
+                .map_err(|err| anyhow::anyhow!("Can't delete {}: {}", key, err))?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits `s3://bucket/prefix` into the bucket and the key prefix
+fn parse_url(url: &str) -> anyhow::Result<(String, String)> {
+    let rest = url
+        .strip_prefix(S3_SCHEME)
+        .ok_or_else(|| anyhow::anyhow!("wrong S3 url '{}'", url))?;
+    let (bucket, root) = rest.split_once('/').unwrap_or((rest, ""));
+    let root = root.trim_matches('/');
+    if bucket.is_empty() {
+        return Err(anyhow::anyhow!("no bucket in S3 url '{}'", url));
+    }
+    validate_folder(root).map_err(|_| anyhow::anyhow!("wrong prefix in S3 url '{}'", url))?;
+    Ok((bucket.to_string(), root.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Bytes;
+    use object_store::memory::InMemory;
+    use test_case::test_case;
+
+    fn make_filer() -> (Arc<InMemory>, S3Filer) {
+        let store = Arc::new(InMemory::new());
+        (store.clone(), S3Filer::with_store(store, "asr"))
+    }
+
+    async fn read(store: &InMemory, key: &str) -> Option<String> {
+        match store.get(&Path::from(key)).await {
+            Ok(v) => Some(String::from_utf8(v.bytes().await.unwrap().to_vec()).unwrap()),
+            Err(_) => None,
+        }
+    }
+
+    #[test_case("s3://bucket", "bucket", ""; "bucket")]
+    #[test_case("s3://bucket/", "bucket", ""; "trailing slash")]
+    #[test_case("s3://bucket/asr/data", "bucket", "asr/data"; "prefix")]
+    fn test_parse_url(url: &str, bucket: &str, root: &str) {
+        assert_eq!(
+            (bucket.to_string(), root.to_string()),
+            parse_url(url).unwrap()
+        );
+    }
+
+    #[test_case("s3://"; "no bucket")]
+    #[test_case("s3:///asr"; "empty bucket")]
+    #[test_case("s3://bucket/a/../b"; "parent")]
+    #[test_case("/data"; "not s3")]
+    fn test_parse_url_fail(url: &str) {
+        assert!(parse_url(url).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_save_and_read() {
+        let (store, f) = make_filer();
+        let stream = futures::stream::iter(vec![
+            Ok(Bytes::from_static(b"au")),
+            Ok(Bytes::from_static(b"dio")),
+        ]);
+        f.save_stream("a.wav", "incoming/team", Box::pin(stream))
+            .await
+            .unwrap();
+        assert_eq!(
+            Some("audio".to_string()),
+            read(&store, "asr/incoming/team/a.wav").await
+        );
+        f.save_txt("a.meta", "incoming/team", "{}").await.unwrap();
+        assert_eq!("{}", f.read_txt("a.meta", "incoming/team").await.unwrap());
+        assert_eq!(
+            b"aud".to_vec(),
+            f.read_head("a.wav", "incoming/team", 3).await.unwrap()
+        );
+        assert_eq!(
+            b"audio".to_vec(),
+            f.read_head("a.wav", "incoming/team", 100).await.unwrap()
+        );
+        assert!(f.read_stream("b.wav", "incoming").await.unwrap().is_none());
+        assert!(f.read_txt("b.meta", "incoming").await.is_err());
+        assert!(f.save_txt("../a.meta", "incoming", "{}").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_move_to() {
+        let (store, f) = make_filer();
+        f.save_txt("a.wav", "incoming", "audio").await.unwrap();
+        f.save_txt("a.wav", "working", "old").await.unwrap();
+        assert_eq!(
+            "a.1.wav",
+            f.non_existing_name("a.wav", "working").await.unwrap()
+        );
+        f.move_to("a.wav", "a.wav", "incoming", "working")
+            .await
+            .unwrap();
+        assert_eq!(
+            Some("audio".to_string()),
+            read(&store, "asr/working/a.wav").await
+        );
+        assert!(!f.exists("a.wav", "incoming").await.unwrap());
+        assert!(f
+            .move_to("a.wav", "a.wav", "incoming", "working")
+            .await
+            .is_err());
+        assert!(f.delete("a.wav", "incoming").await.is_err());
+        f.delete("a.wav", "working").await.unwrap();
+        assert!(!f.exists("a.wav", "working").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_local_file() {
+        let (_store, f) = make_filer();
+        f.save_txt("000.wav", "working/.chunks/1", "audio")
+            .await
+            .unwrap();
+        let local = f.local_file("000.wav", "working/.chunks/1").await.unwrap();
+        let path = local.path().to_path_buf();
+        assert_eq!("audio", std::fs::read_to_string(&path).unwrap());
+        f.save_file("001.wav", "working/.chunks/1", &path)
+            .await
+            .unwrap();
+        drop(local);
+        assert!(!path.exists());
+        assert!(f.exists("001.wav", "working/.chunks/1").await.unwrap());
+        assert!(f.local_file("002.wav", "working/.chunks/1").await.is_err());
+
+        f.save_txt("001.wav", "working/.chunks/10", "other")
+            .await
+            .unwrap();
+        f.delete_dir("working/.chunks/1").await.unwrap();
+        assert!(!f.exists("000.wav", "working/.chunks/1").await.unwrap());
+        assert!(f.exists("001.wav", "working/.chunks/10").await.unwrap());
+    }
+
+    /// Runs against a real S3 compatible storage, e.g. a local MinIO:
+    /// `docker run -p 9000:9000 minio/minio server /data`, create the bucket, then
+    /// `S3_TEST_URL=s3://test/asr AWS_ENDPOINT=http://localhost:9000 AWS_ALLOW_HTTP=true
+    /// AWS_ACCESS_KEY_ID=minioadmin AWS_SECRET_ACCESS_KEY=minioadmin cargo test s3`
+    #[tokio::test]
+    async fn test_s3() {
+        let Ok(url) = std::env::var("S3_TEST_URL") else {
+            return;
+        };
+        let f = S3Filer::new(&url).unwrap();
+        f.save_txt("a.wav", "incoming", "audio").await.unwrap();
+        f.move_to("a.wav", "a.wav", "incoming", "working")
+            .await
+            .unwrap();
+        assert_eq!("audio", f.read_txt("a.wav", "working").await.unwrap());
+        let local = f.local_file("a.wav", "working").await.unwrap();
+        assert_eq!("audio", std::fs::read_to_string(local.path()).unwrap());
+        f.delete_dir("working").await.unwrap();
+        assert!(!f.exists("a.wav", "working").await.unwrap());
+    }
+}
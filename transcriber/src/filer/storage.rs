@@ -0,0 +1,175 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use super::{file::Filer, s3::S3Filer};
+use crate::{Storage, DIR_FAILED, DIR_INCOMING, DIR_PROCESSED, DIR_WORKING};
+
+/// URL scheme of the S3 compatible storage base dir
+pub const S3_SCHEME: &str = "s3://";
+
+/// Locations of the top level folders outside of the base dir, e.g. incoming on the network
+/// share and processed on the archive storage
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct Dirs {
+    /// Incoming dir, `<base_dir>/incoming` if not set
+    #[arg(long, env)]
+    pub incoming_dir: Option<String>,
+
+    /// Working dir, `<base_dir>/working` if not set. Keep it on the local disk
+    #[arg(long, env)]
+    pub working_dir: Option<String>,
+
+    /// Processed dir, `<base_dir>/processed` if not set
+    #[arg(long, env)]
+    pub processed_dir: Option<String>,
+
+    /// Failed dir, `<base_dir>/failed` if not set
+    #[arg(long, env)]
+    pub failed_dir: Option<String>,
+}
+
+impl Dirs {
+    /// The set folders with their dirs
+    fn set(&self) -> Vec<(&'static str, &str)> {
+        [
+            (DIR_INCOMING, &self.incoming_dir),
+            (DIR_WORKING, &self.working_dir),
+            (DIR_PROCESSED, &self.processed_dir),
+            (DIR_FAILED, &self.failed_dir),
+        ]
+        .into_iter()
+        .filter_map(|(folder, dir)| dir.as_deref().map(|dir| (folder, dir)))
+        .collect()
+    }
+}
+
+/// Opens the storage by the base dir: `s3://bucket/prefix` goes to the S3 compatible storage
+/// configured by the `AWS_*` env variables, anything else is a local dir
+pub fn open(base_dir: &str, dirs: &Dirs) -> anyhow::Result<Arc<dyn Storage + Send + Sync>> {
+    let set = dirs.set();
+    if base_dir.starts_with(S3_SCHEME) {
+        if !set.is_empty() {
+            return Err(anyhow::anyhow!(
+                "separate dirs are not supported for {base_dir}"
+            ));
+        }
+        return Ok(Arc::new(S3Filer::new(base_dir)?));
+    }
+    Ok(Arc::new(local(base_dir, dirs)))
+}
+
+/// The local dir storage with the folders moved by `dirs`
+pub fn local(base_dir: &str, dirs: &Dirs) -> Filer {
+    dirs.set()
+        .into_iter()
+        .fold(Filer::new(base_dir), |f, (folder, dir)| {
+            f.with_dir(folder, dir)
+        })
+}
+
+/// The file on the local disk, the temporary copy is removed on drop
+#[derive(Debug)]
+pub struct LocalFile {
+    path: PathBuf,
+    temp_dir: Option<PathBuf>,
+}
+
+impl LocalFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            temp_dir: None,
+        }
+    }
+
+    /// The copy in `temp_dir`, the dir is removed with everything inside on drop
+    pub fn temp(path: PathBuf, temp_dir: PathBuf) -> Self {
+        Self {
+            path,
+            temp_dir: Some(temp_dir),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for LocalFile {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.temp_dir {
+            if let Err(err) = std::fs::remove_dir_all(dir) {
+                log::warn!("can't remove {}: {}", dir.display(), err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(open(dir.path().to_str().unwrap(), &Dirs::default()).is_ok());
+        assert!(open("s3://", &Dirs::default()).is_err());
+        let dirs = Dirs {
+            processed_dir: Some("/archive".to_string()),
+            ..Default::default()
+        };
+        assert!(open("s3://bucket", &dirs).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_local_dirs() {
+        let (base, incoming, processed) = (
+            tempfile::tempdir().unwrap(),
+            tempfile::tempdir().unwrap(),
+            tempfile::tempdir().unwrap(),
+        );
+        let dirs = Dirs {
+            incoming_dir: Some(incoming.path().to_str().unwrap().to_string()),
+            processed_dir: Some(processed.path().to_str().unwrap().to_string()),
+            ..Default::default()
+        };
+        let f = local(base.path().to_str().unwrap(), &dirs);
+        assert_eq!(
+            incoming.path().join("team"),
+            f.dir("incoming/team").unwrap()
+        );
+        assert_eq!(base.path().join("working"), f.dir("working").unwrap());
+
+        f.save_txt("a.wav", "incoming/team", "audio").await.unwrap();
+        f.move_to("a.wav", "a.wav", "incoming/team", "working/team")
+            .await
+            .unwrap();
+        f.move_to("a.wav", "b.wav", "working/team", "processed/team")
+            .await
+            .unwrap();
+        assert_eq!(
+            "audio",
+            std::fs::read_to_string(processed.path().join("team/b.wav")).unwrap()
+        );
+        assert!(!incoming.path().join("team/a.wav").exists());
+        assert!(!base.path().join("working/team/a.wav").exists());
+    }
+
+    #[test]
+    fn test_local_file_temp() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp = dir.path().join("tmp");
+        std::fs::create_dir_all(&temp).unwrap();
+        std::fs::write(temp.join("a.wav"), "audio").unwrap();
+        let file = LocalFile::temp(temp.join("a.wav"), temp.clone());
+        assert!(file.path().exists());
+        drop(file);
+        assert!(!temp.exists());
+
+        std::fs::write(dir.path().join("b.wav"), "audio").unwrap();
+        drop(LocalFile::new(dir.path().join("b.wav")));
+        assert!(dir.path().join("b.wav").exists());
+    }
+}
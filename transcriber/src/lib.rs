@@ -1,15 +1,26 @@
 use async_trait::async_trait;
+use axum::body::Bytes;
+use filer::file::make_new_name;
+use filer::storage::LocalFile;
+use futures::Stream;
+use model::models::WorkStatus;
 use pgmq::Message;
 use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::time::Duration;
 use tokio::signal;
 
 pub mod asr;
+pub mod audio;
 pub mod data;
 pub mod filer;
 pub mod model;
 pub mod postgres;
+pub mod transcript;
 
 pub const INPUT_QUEUE: &str = "asr_input";
+pub const STATUS_QUEUE: &str = "asr_status";
 pub const RESULT_QUEUE: &str = "asr_result";
 pub const CLEAN_QUEUE: &str = "asr_clean";
 
@@ -18,6 +29,9 @@ pub const DIR_WORKING: &str = "working";
 pub const DIR_PROCESSED: &str = "processed";
 pub const DIR_FAILED: &str = "failed";
 pub const INFO_EXTENSION: &str = ".meta";
+pub const FOLDER_CONFIG: &str = ".config";
+/// Split long audio parts, `working/.chunks/<job id>`
+pub const DIR_CHUNKS: &str = ".chunks";
 
 pub const ASR_FILE_RES: &str = "resultFinal.txt";
 pub const ASR_FILE_LAT: &str = "lat.restored.txt";
@@ -30,15 +44,102 @@ where
     async fn send(&self, data: T) -> anyhow::Result<()>;
 }
 
+#[async_trait]
+pub trait QDelaySender<T>
+where
+    T: Send + Sync,
+{
+    async fn send_delay(&self, data: T, delay: Duration) -> anyhow::Result<()>;
+}
+
 #[async_trait]
 pub trait QProcessor<T>
 where
     T: Send + Sync,
 {
-    async fn process<F, Fut>(&self, func: F) -> anyhow::Result<bool>
+    /// Processes one message, `on_exhausted` gets the message given up after the max retries
+    async fn process<F, Fut, X, XFut>(&self, func: F, on_exhausted: X) -> anyhow::Result<bool>
     where
         F: Fn(Message<T>) -> Fut + Send,
-        Fut: Future<Output = anyhow::Result<bool>> + Send;
+        Fut: Future<Output = anyhow::Result<bool>> + Send,
+        X: Fn(Message<T>, String) -> XFut + Send,
+        XFut: Future<Output = anyhow::Result<()>> + Send;
+}
+
+/// Keeps the job lifecycle state in `work_data`
+#[async_trait]
+pub trait StatusTracker {
+    async fn set_status(
+        &self,
+        id: &str,
+        status: WorkStatus,
+        error: Option<&str>,
+    ) -> anyhow::Result<()>;
+    async fn set_progress(&self, id: &str, progress: i32) -> anyhow::Result<()>;
+    async fn set_result_file(&self, id: &str, file_name: &str) -> anyhow::Result<()>;
+    /// The saved result file of the job, `None` if not set
+    async fn result_file(&self, id: &str) -> anyhow::Result<Option<String>>;
+    async fn set_chunks(&self, id: &str, count: i32) -> anyhow::Result<()>;
+    async fn set_cleaned(&self, external_id: &str) -> anyhow::Result<()>;
+    /// Resets the failed job for its replay: `queued` if it is not uploaded yet, `transcribing`
+    /// otherwise. The error and the result file are cleared, other states are kept
+    async fn restart(&self, id: &str) -> anyhow::Result<()>;
+    /// Takes the job's lock until the returned guard is dropped, the other takers wait.
+    /// The lock is released if the holder crashes too
+    async fn lock(&self, id: &str) -> anyhow::Result<Box<dyn Send + Sync>>;
+}
+
+/// File content streamed to or from the storage
+pub type ByteStream<'a> = Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send + 'a>>;
+
+/// Keeps the job files in the `incoming`, `working`, `processed` and `failed` folders.
+/// `folder` is relative to the storage root, `f_name` is a plain file name
+#[async_trait]
+pub trait Storage {
+    async fn save_stream(
+        &self,
+        f_name: &str,
+        folder: &str,
+        stream: ByteStream<'_>,
+    ) -> anyhow::Result<()>;
+    async fn save_txt(&self, f_name: &str, folder: &str, txt: &str) -> anyhow::Result<()>;
+    /// Copies the local file in
+    async fn save_file(&self, f_name: &str, folder: &str, src: &Path) -> anyhow::Result<()>;
+    async fn read_txt(&self, f_name: &str, folder: &str) -> anyhow::Result<String>;
+    /// Reads up to `size` first bytes of the file
+    async fn read_head(&self, f_name: &str, folder: &str, size: usize) -> anyhow::Result<Vec<u8>>;
+    /// Opens the file for streaming, `None` if the file does not exist
+    async fn read_stream(
+        &self,
+        f_name: &str,
+        folder: &str,
+    ) -> anyhow::Result<Option<ByteStream<'static>>>;
+    /// The file on the local disk for the audio tools, a temporary copy if the storage is remote
+    async fn local_file(&self, f_name: &str, folder: &str) -> anyhow::Result<LocalFile>;
+    async fn exists(&self, f_name: &str, folder: &str) -> anyhow::Result<bool>;
+    /// Moves the file, overwrites `to_name` if it exists
+    async fn move_to(
+        &self,
+        f_name: &str,
+        to_name: &str,
+        dir_from: &str,
+        dir_to: &str,
+    ) -> anyhow::Result<()>;
+    async fn delete(&self, f_name: &str, folder: &str) -> anyhow::Result<()>;
+    /// Removes the folder with everything inside
+    async fn delete_dir(&self, folder: &str) -> anyhow::Result<()>;
+
+    /// `f_name` or `f_name` with a number added, so no file in `folder` is overwritten
+    async fn non_existing_name(&self, f_name: &str, folder: &str) -> anyhow::Result<String> {
+        let mut i = 0;
+        loop {
+            let new_name = make_new_name(f_name, i);
+            if !self.exists(&new_name, folder).await? {
+                return Ok(new_name);
+            }
+            i += 1;
+        }
+    }
 }
 
 pub async fn shutdown_signal() {
@@ -14,4 +14,171 @@ pub struct WorkData {
     pub updated: NaiveDateTime,
     pub error_msg: String,
     pub upload_time: Option<NaiveDateTime>,
+    pub model: String,
+    pub status: String,
+    pub progress: i32,
+    pub upload_start_time: Option<NaiveDateTime>,
+    pub fetch_time: Option<NaiveDateTime>,
+    pub finish_time: Option<NaiveDateTime>,
+    pub clean_time: Option<NaiveDateTime>,
+    pub office: String,
+    /// Name of the transcribed audio in `processed`, the outputs are named after it.
+    /// The failed audio's name in `failed`, the job replay finds it by this name
+    pub result_file: String,
+    /// Dir relative to `incoming`, mirrored in the other folders
+    pub sub_dir: String,
+    /// Seconds, `None` if the audio was not probed
+    pub duration: Option<f64>,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<i32>,
+    pub codec: String,
+    /// Job of the original audio if this one transcribes its chunk
+    pub parent_id: String,
+    /// Count of the chunks the audio was split into, 0 - transcribed as a whole
+    pub chunks: i32,
+}
+
+/// Audio file state in `incoming` at the last scan
+#[derive(Queryable, Selectable, Insertable, Debug, Clone, PartialEq)]
+#[diesel(table_name = crate::model::schema::incoming_files)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct IncomingFile {
+    /// Scanned incoming dir
+    pub base_dir: String,
+    pub name: String,
+    pub size: i64,
+    pub modified: NaiveDateTime,
+    /// When the file was first seen with this size and modification time
+    pub seen: NaiveDateTime,
+}
+
+/// Job lifecycle state kept in `work_data.status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkStatus {
+    Queued,
+    Uploading,
+    Transcribing,
+    Fetching,
+    Done,
+    Failed,
+    /// Not sent to the recognizer, e.g. no speech in the audio
+    Skipped,
+}
+
+impl WorkStatus {
+    pub const ALL: [WorkStatus; 7] = [
+        WorkStatus::Queued,
+        WorkStatus::Uploading,
+        WorkStatus::Transcribing,
+        WorkStatus::Fetching,
+        WorkStatus::Done,
+        WorkStatus::Failed,
+        WorkStatus::Skipped,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkStatus::Queued => "queued",
+            WorkStatus::Uploading => "uploading",
+            WorkStatus::Transcribing => "transcribing",
+            WorkStatus::Fetching => "fetching",
+            WorkStatus::Done => "done",
+            WorkStatus::Failed => "failed",
+            WorkStatus::Skipped => "skipped",
+        }
+    }
+
+    /// States the job may move to this state from. A state may repeat on a retry,
+    /// a failed job may be restarted by replaying its queue message
+    pub fn allowed_from(&self) -> &'static [WorkStatus] {
+        match self {
+            WorkStatus::Queued => &[],
+            WorkStatus::Uploading => &[
+                WorkStatus::Queued,
+                WorkStatus::Uploading,
+                WorkStatus::Failed,
+            ],
+            WorkStatus::Transcribing => &[
+                WorkStatus::Queued,
+                WorkStatus::Uploading,
+                WorkStatus::Transcribing,
+                WorkStatus::Failed,
+            ],
+            WorkStatus::Fetching => &[
+                WorkStatus::Transcribing,
+                WorkStatus::Fetching,
+                WorkStatus::Failed,
+            ],
+            WorkStatus::Done => &[WorkStatus::Fetching],
+            WorkStatus::Failed => &[
+                WorkStatus::Queued,
+                WorkStatus::Uploading,
+                WorkStatus::Transcribing,
+                WorkStatus::Fetching,
+                WorkStatus::Failed,
+            ],
+            WorkStatus::Skipped => &[
+                WorkStatus::Queued,
+                WorkStatus::Uploading,
+                WorkStatus::Failed,
+            ],
+        }
+    }
+
+    pub fn can_move_to(&self, next: WorkStatus) -> bool {
+        next.allowed_from().contains(self)
+    }
+
+    pub fn allowed_from_str(&self) -> Vec<&'static str> {
+        self.allowed_from().iter().map(|s| s.as_str()).collect()
+    }
+}
+
+impl std::fmt::Display for WorkStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for WorkStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        WorkStatus::ALL
+            .iter()
+            .find(|v| v.as_str() == s)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("unknown status '{}'", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(WorkStatus::Queued, WorkStatus::Uploading, true; "start upload")]
+    #[test_case(WorkStatus::Uploading, WorkStatus::Uploading, true; "retry upload")]
+    #[test_case(WorkStatus::Uploading, WorkStatus::Transcribing, true; "uploaded")]
+    #[test_case(WorkStatus::Transcribing, WorkStatus::Fetching, true; "transcribed")]
+    #[test_case(WorkStatus::Fetching, WorkStatus::Done, true; "done")]
+    #[test_case(WorkStatus::Transcribing, WorkStatus::Failed, true; "failed")]
+    #[test_case(WorkStatus::Failed, WorkStatus::Uploading, true; "replay")]
+    #[test_case(WorkStatus::Queued, WorkStatus::Skipped, true; "no speech")]
+    #[test_case(WorkStatus::Queued, WorkStatus::Done, false; "skip to done")]
+    #[test_case(WorkStatus::Transcribing, WorkStatus::Skipped, false; "skip uploaded")]
+    #[test_case(WorkStatus::Done, WorkStatus::Failed, false; "fail done")]
+    #[test_case(WorkStatus::Done, WorkStatus::Uploading, false; "restart done")]
+    #[test_case(WorkStatus::Fetching, WorkStatus::Queued, false; "back to queue")]
+    fn test_can_move_to(from: WorkStatus, to: WorkStatus, expected: bool) {
+        assert_eq!(expected, from.can_move_to(to));
+    }
+
+    #[test]
+    fn test_from_str() {
+        for s in WorkStatus::ALL {
+            assert_eq!(s, s.as_str().parse::<WorkStatus>().unwrap());
+        }
+        assert!("olia".parse::<WorkStatus>().is_err());
+    }
 }
@@ -1,5 +1,15 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    incoming_files (base_dir, name) {
+        base_dir -> Text,
+        name -> Text,
+        size -> Int8,
+        modified -> Timestamp,
+        seen -> Timestamp,
+    }
+}
+
 diesel::table! {
     work_data (id) {
         id -> Text,
@@ -11,5 +21,23 @@ diesel::table! {
         updated -> Timestamp,
         error_msg -> Text,
         upload_time -> Nullable<Timestamp>,
+        model -> Text,
+        status -> Text,
+        progress -> Int4,
+        upload_start_time -> Nullable<Timestamp>,
+        fetch_time -> Nullable<Timestamp>,
+        finish_time -> Nullable<Timestamp>,
+        clean_time -> Nullable<Timestamp>,
+        office -> Text,
+        result_file -> Text,
+        sub_dir -> Text,
+        duration -> Nullable<Float8>,
+        sample_rate -> Nullable<Int4>,
+        channels -> Nullable<Int4>,
+        codec -> Text,
+        parent_id -> Text,
+        chunks -> Int4,
     }
 }
+
+diesel::allow_tables_to_appear_in_same_query!(incoming_files, work_data,);
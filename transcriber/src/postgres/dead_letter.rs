@@ -0,0 +1,150 @@
+use std::error::Error;
+use std::future::Future;
+
+use pgmq::{Message, PGMQueue};
+use sqlx::Row;
+
+use super::queue::{dead_letter_name, notify, DeadLetter};
+
+/// Inspects and replays the dead letters of one queue
+pub struct DeadLetters {
+    pgmq: PGMQueue,
+    queue_name: String,
+    dead_name: String,
+}
+
+impl DeadLetters {
+    pub async fn new(p_url: &str, queue_name: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        check_name(queue_name)?;
+        log::info!("Init dead letters for: {queue_name}");
+        let queue: PGMQueue = PGMQueue::new(p_url.to_string())
+            .await
+            .map_err(|err| format!("Can't connect to postgres: {}", err))?;
+        let dead_name = dead_letter_name(queue_name);
+        queue
+            .create(&dead_name)
+            .await
+            .map_err(|err| format!("Can't create queue '{dead_name}': {}", err))?;
+        Ok(Self {
+            pgmq: queue,
+            queue_name: queue_name.to_string(),
+            dead_name,
+        })
+    }
+
+    pub async fn list(&self, limit: i64) -> anyhow::Result<Vec<Message<DeadLetter>>> {
+        let sql = format!(
+            "SELECT msg_id, vt, enqueued_at, read_ct, message FROM pgmq.q_{} ORDER BY msg_id LIMIT $1",
+            self.dead_name
+        );
+        let rows = sqlx::query(&sql)
+            .bind(limit)
+            .fetch_all(&self.pgmq.connection)
+            .await?;
+        rows.iter().map(to_message).collect()
+    }
+
+    pub async fn get(&self, id: i64) -> anyhow::Result<Option<Message<DeadLetter>>> {
+        let sql = format!(
+            "SELECT msg_id, vt, enqueued_at, read_ct, message FROM pgmq.q_{} WHERE msg_id = $1",
+            self.dead_name
+        );
+        let row = sqlx::query(&sql)
+            .bind(id)
+            .fetch_optional(&self.pgmq.connection)
+            .await?;
+        row.as_ref().map(to_message).transpose()
+    }
+
+    /// Moves the original message back to the queue, all messages if `id` is `None`.
+    /// `restore` prepares the job of each message first, the message it fails for stays here.
+    /// Returns the number of replayed messages
+    pub async fn replay<F, Fut>(&self, id: Option<i64>, restore: F) -> anyhow::Result<u64>
+    where
+        F: Fn(serde_json::Value) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>>,
+    {
+        let items = match id {
+            Some(id) => vec![self
+                .get(id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("no message {}", id))?],
+            None => self.list(i64::MAX).await?,
+        };
+        let mut res = 0;
+        for item in items {
+            match restore(item.message.message).await {
+                Ok(()) => res += self.requeue(item.msg_id).await?,
+                Err(err) if id.is_some() => return Err(err),
+                Err(err) => log::error!("can't restore {}: {}", item.msg_id, err),
+            }
+        }
+        if res > 0 {
+            // don't fail here, the messages are already back and the consumers poll anyway
+            if let Err(err) = notify(&self.pgmq.connection, &self.queue_name).await {
+                log::warn!("can't notify {}: {}", self.queue_name, err);
+            }
+        }
+        Ok(res)
+    }
+
+    async fn requeue(&self, id: i64) -> anyhow::Result<u64> {
+        let sql = format!(
+            "WITH d AS (DELETE FROM pgmq.q_{} WHERE msg_id = $1 RETURNING message) \
+             INSERT INTO pgmq.q_{} (vt, message) SELECT clock_timestamp(), message->'message' FROM d",
+            self.dead_name, self.queue_name
+        );
+        let res = sqlx::query(&sql)
+            .bind(id)
+            .execute(&self.pgmq.connection)
+            .await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Drops the dead letters, all messages if `id` is `None`.
+    /// Returns the number of deleted messages
+    pub async fn purge(&self, id: Option<i64>) -> anyhow::Result<u64> {
+        match id {
+            Some(id) => Ok(self.pgmq.delete(&self.dead_name, id).await?),
+            None => Ok(self.pgmq.purge(&self.dead_name).await?),
+        }
+    }
+}
+
+fn to_message(row: &sqlx::postgres::PgRow) -> anyhow::Result<Message<DeadLetter>> {
+    let message: serde_json::Value = row.try_get("message")?;
+    Ok(Message {
+        msg_id: row.try_get("msg_id")?,
+        vt: row.try_get("vt")?,
+        enqueued_at: row.try_get("enqueued_at")?,
+        read_ct: row.try_get("read_ct")?,
+        message: serde_json::from_value(message)?,
+    })
+}
+
+/// Queue names go into the SQL as table names
+fn check_name(name: &str) -> anyhow::Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        return Err(anyhow::anyhow!("wrong queue name '{}'", name));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("asr_input", true; "ok")]
+    #[test_case("asr_result2", true; "digits")]
+    #[test_case("", false; "empty")]
+    #[test_case("asr;drop", false; "sql")]
+    #[test_case("ASR", false; "upper")]
+    fn test_check_name(name: &str, expected: bool) {
+        assert_eq!(expected, check_name(name).is_ok());
+    }
+}
@@ -0,0 +1,75 @@
+use deadpool_diesel::postgres::Pool;
+use diesel::{upsert::excluded, ExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper};
+
+use crate::model::{models::IncomingFile, schema};
+
+/// Keeps the incoming files seen by the previous scans
+#[derive(Clone)]
+pub struct IncomingStore {
+    pool: Pool,
+}
+
+impl IncomingStore {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn list(&self, base_dir_v: &str) -> anyhow::Result<Vec<IncomingFile>> {
+        let base_dir_v = base_dir_v.to_string();
+        let conn = self.pool.get().await?;
+        let res = conn
+            .interact(move |conn| {
+                use schema::incoming_files::dsl::*;
+                incoming_files
+                    .filter(base_dir.eq(base_dir_v))
+                    .select(IncomingFile::as_select())
+                    .load(conn)
+            })
+            .await
+            .map_err(|err| anyhow::anyhow!("can't get incoming files: {}", err))??;
+        Ok(res)
+    }
+
+    pub async fn save(&self, files: Vec<IncomingFile>) -> anyhow::Result<()> {
+        if files.is_empty() {
+            return Ok(());
+        }
+        let conn = self.pool.get().await?;
+        _ = conn
+            .interact(move |conn| {
+                use schema::incoming_files::dsl::*;
+                diesel::insert_into(incoming_files)
+                    .values(&files)
+                    .on_conflict((base_dir, name))
+                    .do_update()
+                    .set((
+                        size.eq(excluded(size)),
+                        modified.eq(excluded(modified)),
+                        seen.eq(excluded(seen)),
+                    ))
+                    .execute(conn)
+            })
+            .await
+            .map_err(|err| anyhow::anyhow!("can't save incoming files: {}", err))??;
+        Ok(())
+    }
+
+    pub async fn delete(&self, base_dir_v: &str, names: Vec<String>) -> anyhow::Result<()> {
+        if names.is_empty() {
+            return Ok(());
+        }
+        let base_dir_v = base_dir_v.to_string();
+        let conn = self.pool.get().await?;
+        _ = conn
+            .interact(move |conn| {
+                use schema::incoming_files::dsl::*;
+                diesel::delete(incoming_files)
+                    .filter(base_dir.eq(base_dir_v))
+                    .filter(name.eq_any(names))
+                    .execute(conn)
+            })
+            .await
+            .map_err(|err| anyhow::anyhow!("can't delete incoming files: {}", err))??;
+        Ok(())
+    }
+}
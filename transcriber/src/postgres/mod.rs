@@ -1 +1,4 @@
+pub mod dead_letter;
+pub mod incoming;
 pub mod queue;
+pub mod work;
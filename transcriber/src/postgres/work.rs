@@ -0,0 +1,426 @@
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use deadpool_diesel::postgres::{Object, Pool};
+use diesel::{
+    pg::Pg, ExpressionMethods, OptionalExtension, PgTextExpressionMethods, QueryDsl, RunQueryDsl,
+    SelectableHelper,
+};
+
+use crate::{
+    data::api::ASRMessage,
+    model::{
+        models::{WorkData, WorkStatus},
+        schema,
+    },
+    StatusTracker,
+};
+
+/// Job list conditions, `None` fields are not checked
+#[derive(Debug, Clone, Default)]
+pub struct WorkFilter {
+    pub status: Option<WorkStatus>,
+    pub office: Option<String>,
+    /// Substring of the file name
+    pub file_name: Option<String>,
+    /// Created at or after
+    pub from: Option<NaiveDateTime>,
+    /// Created before
+    pub to: Option<NaiveDateTime>,
+    pub offset: i64,
+    pub limit: i64,
+}
+
+#[derive(Clone)]
+pub struct WorkStore {
+    pool: Pool,
+}
+
+impl WorkStore {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn get(&self, id_v: &str) -> anyhow::Result<Option<WorkData>> {
+        let id_v = id_v.to_string();
+        let conn = self.pool.get().await?;
+        let res = conn
+            .interact(move |conn| {
+                use schema::work_data::dsl::*;
+                work_data
+                    .filter(id.eq(id_v))
+                    .select(WorkData::as_select())
+                    .first(conn)
+                    .optional()
+            })
+            .await
+            .map_err(|err| anyhow::anyhow!("can't get work data: {}", err))??;
+        Ok(res)
+    }
+
+    /// Registers a queued job, an existing job is left as is
+    pub async fn insert(&self, msg: &ASRMessage) -> anyhow::Result<()> {
+        let msg = msg.clone();
+        let conn = self.pool.get().await?;
+        _ = conn
+            .interact(move |conn| {
+                use schema::work_data::dsl::*;
+                diesel::insert_into(work_data)
+                    .values((
+                        id.eq(msg.id),
+                        file_name.eq(msg.file),
+                        base_dir.eq(msg.base_dir),
+                        sub_dir.eq(msg.sub_dir),
+                        external_id.eq(""),
+                        model.eq(msg.model.unwrap_or_default()),
+                        office.eq(msg.office.unwrap_or_default()),
+                        duration.eq(msg.audio.as_ref().map(|v| v.duration)),
+                        sample_rate.eq(msg.audio.as_ref().map(|v| v.sample_rate as i32)),
+                        channels.eq(msg.audio.as_ref().map(|v| v.channels as i32)),
+                        codec.eq(msg.audio.map(|v| v.codec).unwrap_or_default()),
+                    ))
+                    .on_conflict_do_nothing()
+                    .execute(conn)
+            })
+            .await
+            .map_err(|err| anyhow::anyhow!("can't insert work data: {}", err))??;
+        Ok(())
+    }
+
+    /// Returns the page of jobs, newest first, and the total count of the matching jobs
+    pub async fn list(&self, filter: WorkFilter) -> anyhow::Result<(Vec<WorkData>, i64)> {
+        let conn = self.pool.get().await?;
+        let res = conn
+            .interact(move |conn| {
+                use schema::work_data::dsl::*;
+                let total: i64 = filtered(&filter).count().get_result(conn)?;
+                let items: Vec<WorkData> = filtered(&filter)
+                    .order(created.desc())
+                    .offset(filter.offset)
+                    .limit(filter.limit)
+                    .select(WorkData::as_select())
+                    .load(conn)?;
+                Ok::<_, diesel::result::Error>((items, total))
+            })
+            .await
+            .map_err(|err| anyhow::anyhow!("can't list work data: {}", err))??;
+        Ok(res)
+    }
+}
+
+fn filtered(filter: &WorkFilter) -> schema::work_data::BoxedQuery<'static, Pg> {
+    use schema::work_data::dsl::*;
+    // chunks are followed through their parent job
+    let mut query = work_data.filter(parent_id.eq("")).into_boxed();
+    if let Some(v) = filter.status {
+        query = query.filter(status.eq(v.as_str()));
+    }
+    if let Some(v) = &filter.office {
+        query = query.filter(office.eq(v.clone()));
+    }
+    if let Some(v) = &filter.file_name {
+        query = query.filter(file_name.ilike(format!("%{}%", escape_like(v))));
+    }
+    if let Some(v) = filter.from {
+        query = query.filter(created.ge(v));
+    }
+    if let Some(v) = filter.to {
+        query = query.filter(created.lt(v));
+    }
+    query
+}
+
+fn escape_like(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[async_trait]
+impl StatusTracker for WorkStore {
+    async fn set_status(
+        &self,
+        id_v: &str,
+        status_v: WorkStatus,
+        error: Option<&str>,
+    ) -> anyhow::Result<()> {
+        log::info!("set status {}: {}", id_v, status_v);
+        let id_v = id_v.to_string();
+        let error = error.map(|v| v.to_string());
+        let conn = self.pool.get().await?;
+        let updated = conn
+            .interact(move |conn| {
+                use schema::work_data::dsl::*;
+                let now = chrono::Utc::now().naive_utc();
+                let q = diesel::update(work_data)
+                    .filter(id.eq(id_v))
+                    .filter(status.eq_any(status_v.allowed_from_str()));
+                let base = (status.eq(status_v.as_str()), updated.eq(now));
+                match status_v {
+                    WorkStatus::Queued => q.set(base).execute(conn),
+                    WorkStatus::Uploading => q
+                        .set((base, upload_start_time.eq(now), progress.eq(0)))
+                        .execute(conn),
+                    WorkStatus::Transcribing => q
+                        .set((base, upload_time.eq(now), progress.eq(0)))
+                        .execute(conn),
+                    WorkStatus::Fetching => q.set((base, fetch_time.eq(now))).execute(conn),
+                    WorkStatus::Done => q
+                        .set((base, finish_time.eq(now), progress.eq(100)))
+                        .execute(conn),
+                    WorkStatus::Failed | WorkStatus::Skipped => q
+                        .set((
+                            base,
+                            finish_time.eq(now),
+                            error_msg.eq(error.unwrap_or_default()),
+                        ))
+                        .execute(conn),
+                }
+            })
+            .await
+            .map_err(|err| anyhow::anyhow!("can't update work data: {}", err))??;
+        if updated == 0 {
+            log::warn!(
+                "status not changed to {}: no job or wrong transition",
+                status_v
+            );
+        }
+        Ok(())
+    }
+
+    async fn set_progress(&self, id_v: &str, progress_v: i32) -> anyhow::Result<()> {
+        log::debug!("set progress {}: {}", id_v, progress_v);
+        let id_v = id_v.to_string();
+        let conn = self.pool.get().await?;
+        _ = conn
+            .interact(move |conn| {
+                use schema::work_data::dsl::*;
+                diesel::update(work_data)
+                    .filter(id.eq(id_v))
+                    .set((
+                        progress.eq(progress_v),
+                        updated.eq(chrono::Utc::now().naive_utc()),
+                    ))
+                    .execute(conn)
+            })
+            .await
+            .map_err(|err| anyhow::anyhow!("can't update work data: {}", err))??;
+        Ok(())
+    }
+
+    async fn set_result_file(&self, id_v: &str, file_name_v: &str) -> anyhow::Result<()> {
+        log::debug!("set result file {}: {}", id_v, file_name_v);
+        let id_v = id_v.to_string();
+        let file_name_v = file_name_v.to_string();
+        let conn = self.pool.get().await?;
+        _ = conn
+            .interact(move |conn| {
+                use schema::work_data::dsl::*;
+                diesel::update(work_data)
+                    .filter(id.eq(id_v))
+                    .set((
+                        result_file.eq(file_name_v),
+                        updated.eq(chrono::Utc::now().naive_utc()),
+                    ))
+                    .execute(conn)
+            })
+            .await
+            .map_err(|err| anyhow::anyhow!("can't update work data: {}", err))??;
+        Ok(())
+    }
+
+    async fn result_file(&self, id_v: &str) -> anyhow::Result<Option<String>> {
+        let res = self.get(id_v).await?;
+        Ok(res
+            .map(|v| v.result_file)
+            .filter(|v: &String| !v.is_empty()))
+    }
+
+    async fn set_chunks(&self, id_v: &str, chunks_v: i32) -> anyhow::Result<()> {
+        log::debug!("set chunks {}: {}", id_v, chunks_v);
+        let id_v = id_v.to_string();
+        let conn = self.pool.get().await?;
+        _ = conn
+            .interact(move |conn| {
+                use schema::work_data::dsl::*;
+                diesel::update(work_data)
+                    .filter(id.eq(id_v))
+                    .set((
+                        chunks.eq(chunks_v),
+                        updated.eq(chrono::Utc::now().naive_utc()),
+                    ))
+                    .execute(conn)
+            })
+            .await
+            .map_err(|err| anyhow::anyhow!("can't update work data: {}", err))??;
+        Ok(())
+    }
+
+    async fn set_cleaned(&self, external_id_v: &str) -> anyhow::Result<()> {
+        log::debug!("set cleaned {}", external_id_v);
+        let external_id_v = external_id_v.to_string();
+        let conn = self.pool.get().await?;
+        _ = conn
+            .interact(move |conn| {
+                use schema::work_data::dsl::*;
+                let now = chrono::Utc::now().naive_utc();
+                diesel::update(work_data)
+                    .filter(external_id.eq(external_id_v))
+                    .set((clean_time.eq(now), updated.eq(now)))
+                    .execute(conn)
+            })
+            .await
+            .map_err(|err| anyhow::anyhow!("can't update work data: {}", err))??;
+        Ok(())
+    }
+
+    async fn restart(&self, id_v: &str) -> anyhow::Result<()> {
+        log::info!("restart {}", id_v);
+        let id_v = id_v.to_string();
+        let conn = self.pool.get().await?;
+        let updated = conn
+            .interact(move |conn| {
+                use schema::work_data::dsl::*;
+                let now = chrono::Utc::now().naive_utc();
+                let failed = work_data
+                    .filter(id.eq(id_v))
+                    .filter(status.eq(WorkStatus::Failed.as_str()));
+                let reset = (
+                    updated.eq(now),
+                    error_msg.eq(""),
+                    result_file.eq(""),
+                    finish_time.eq(None::<NaiveDateTime>),
+                    progress.eq(0),
+                );
+                let queued = diesel::update(failed.clone().filter(external_id.eq("")))
+                    .set((status.eq(WorkStatus::Queued.as_str()), reset))
+                    .execute(conn)?;
+                let uploaded = diesel::update(failed.filter(external_id.ne("")))
+                    .set((status.eq(WorkStatus::Transcribing.as_str()), reset))
+                    .execute(conn)?;
+                Ok::<usize, diesel::result::Error>(queued + uploaded)
+            })
+            .await
+            .map_err(|err| anyhow::anyhow!("can't update work data: {}", err))??;
+        if updated == 0 {
+            log::warn!("job not restarted: no job or it is not failed");
+        }
+        Ok(())
+    }
+
+    async fn lock(&self, id_v: &str) -> anyhow::Result<Box<dyn Send + Sync>> {
+        log::debug!("lock {}", id_v);
+        let id_v = id_v.to_string();
+        let conn = self.pool.get().await?;
+        conn.interact(move |conn| {
+            diesel::sql_query("SELECT pg_advisory_lock(hashtextextended($1, 0))")
+                .bind::<diesel::sql_types::Text, _>(id_v)
+                .execute(conn)
+        })
+        .await
+        .map_err(|err| anyhow::anyhow!("can't lock job: {}", err))??;
+        Ok(Box::new(JobLock(Some(conn))))
+    }
+}
+
+/// Session lock of the job, the connection is closed with the guard, so the lock never
+/// goes back to the pool
+struct JobLock(Option<Object>);
+
+impl Drop for JobLock {
+    fn drop(&mut self) {
+        if let Some(conn) = self.0.take() {
+            drop(Object::take(conn));
+        }
+    }
+}
+
+/// Tracker keeping the changes in memory, for the worker tests
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct MemTracker {
+    pub statuses: std::sync::Arc<std::sync::Mutex<Vec<(String, WorkStatus)>>>,
+    pub progress: std::sync::Arc<std::sync::Mutex<Vec<(String, i32)>>>,
+    pub cleaned: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    pub result_files: std::sync::Arc<std::sync::Mutex<Vec<(String, String)>>>,
+    pub chunks: std::sync::Arc<std::sync::Mutex<Vec<(String, i32)>>>,
+    pub locks: std::sync::Arc<tokio::sync::Mutex<()>>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl StatusTracker for MemTracker {
+    async fn set_status(
+        &self,
+        id: &str,
+        status: WorkStatus,
+        _error: Option<&str>,
+    ) -> anyhow::Result<()> {
+        self.statuses.lock().unwrap().push((id.to_string(), status));
+        Ok(())
+    }
+
+    async fn set_progress(&self, id: &str, progress: i32) -> anyhow::Result<()> {
+        self.progress
+            .lock()
+            .unwrap()
+            .push((id.to_string(), progress));
+        Ok(())
+    }
+
+    async fn set_result_file(&self, id: &str, file_name: &str) -> anyhow::Result<()> {
+        self.result_files
+            .lock()
+            .unwrap()
+            .push((id.to_string(), file_name.to_string()));
+        Ok(())
+    }
+
+    async fn result_file(&self, id: &str) -> anyhow::Result<Option<String>> {
+        let res = self.result_files.lock().unwrap();
+        Ok(res
+            .iter()
+            .rev()
+            .find(|v| v.0 == id)
+            .map(|v| v.1.clone())
+            .filter(|v| !v.is_empty()))
+    }
+
+    async fn set_chunks(&self, id: &str, count: i32) -> anyhow::Result<()> {
+        self.chunks.lock().unwrap().push((id.to_string(), count));
+        Ok(())
+    }
+
+    async fn set_cleaned(&self, external_id: &str) -> anyhow::Result<()> {
+        self.cleaned.lock().unwrap().push(external_id.to_string());
+        Ok(())
+    }
+
+    async fn restart(&self, id: &str) -> anyhow::Result<()> {
+        self.statuses
+            .lock()
+            .unwrap()
+            .push((id.to_string(), WorkStatus::Queued));
+        self.result_files
+            .lock()
+            .unwrap()
+            .push((id.to_string(), "".to_string()));
+        Ok(())
+    }
+
+    async fn lock(&self, _id: &str) -> anyhow::Result<Box<dyn Send + Sync>> {
+        Ok(Box::new(self.locks.clone().lock_owned().await))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("olia", "olia"; "plain")]
+    #[test_case("a_b%", "a\\_b\\%"; "wildcards")]
+    #[test_case("a\\b", "a\\\\b"; "escape")]
+    fn test_escape_like(v: &str, expected: &str) {
+        assert_eq!(expected, escape_like(v));
+    }
+}
@@ -6,6 +6,8 @@ use thiserror::Error;
 pub enum ApiError {
     #[error("bad request: {0}, details: {1}")]
     BadRequest(String, String),
+    #[error("not found: {0}")]
+    NotFound(String),
     #[error("Server error: {0}`")]
     Server(String),
     #[error(transparent)]
@@ -19,6 +21,10 @@ impl IntoResponse for ApiError {
                 tracing::warn!("{}: {}", msg, details);
                 (StatusCode::BAD_REQUEST, msg)
             }
+            ApiError::NotFound(msg) => {
+                tracing::warn!("not found: {}", msg);
+                (StatusCode::NOT_FOUND, "Not Found".to_string())
+            }
             ApiError::Server(msg) => {
                 tracing::error!("{}", msg);
                 (
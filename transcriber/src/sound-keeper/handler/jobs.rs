@@ -0,0 +1,320 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{self, FromRef, Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use transcriber::{
+    data::format::ResultFormat,
+    filer::file::{make_name, split_path, sub_folder},
+    model::models::{WorkData, WorkStatus},
+    postgres::work::{WorkFilter, WorkStore},
+    Storage, DIR_PROCESSED,
+};
+
+use super::error::ApiError;
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 500;
+
+#[derive(Clone)]
+pub struct JobsState {
+    pub store: WorkStore,
+    pub filer: Arc<dyn Storage + Send + Sync>,
+}
+
+impl FromRef<JobsState> for WorkStore {
+    fn from_ref(state: &JobsState) -> Self {
+        state.store.clone()
+    }
+}
+
+impl FromRef<JobsState> for Arc<dyn Storage + Send + Sync> {
+    fn from_ref(state: &JobsState) -> Self {
+        state.filer.clone()
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct Job {
+    id: String,
+    file: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    dir: String,
+    status: String,
+    progress: i32,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    error: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    model: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    office: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sample_rate: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channels: Option<i32>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    codec: String,
+    /// The long audio is transcribed in this many parts
+    #[serde(skip_serializing_if = "is_zero")]
+    chunks: i32,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    parent: String,
+    created: NaiveDateTime,
+    updated: NaiveDateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upload_start_time: Option<NaiveDateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upload_time: Option<NaiveDateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fetch_time: Option<NaiveDateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finish_time: Option<NaiveDateTime>,
+}
+
+impl From<WorkData> for Job {
+    fn from(v: WorkData) -> Self {
+        Self {
+            id: v.id,
+            file: v.file_name,
+            dir: v.sub_dir,
+            status: v.status,
+            progress: v.progress,
+            error: v.error_msg,
+            model: v.model,
+            office: v.office,
+            duration: v.duration,
+            sample_rate: v.sample_rate,
+            channels: v.channels,
+            codec: v.codec,
+            chunks: v.chunks,
+            parent: v.parent_id,
+            created: v.created,
+            updated: v.updated,
+            upload_start_time: v.upload_start_time,
+            upload_time: v.upload_time,
+            fetch_time: v.fetch_time,
+            finish_time: v.finish_time,
+        }
+    }
+}
+
+fn is_zero(v: &i32) -> bool {
+    *v == 0
+}
+
+#[derive(Serialize, Clone)]
+pub struct JobList {
+    items: Vec<Job>,
+    total: i64,
+    offset: i64,
+    limit: i64,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ListParams {
+    status: Option<String>,
+    office: Option<String>,
+    file: Option<String>,
+    /// First day, inclusive
+    from: Option<NaiveDate>,
+    /// Last day, inclusive
+    to: Option<NaiveDate>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ResultParams {
+    format: Option<String>,
+}
+
+pub async fn get(
+    State(store): State<WorkStore>,
+    Path(id): Path<String>,
+) -> Result<extract::Json<Job>, ApiError> {
+    tracing::debug!(id, "get job");
+    match store.get(&id).await? {
+        Some(v) => Ok(Json(v.into())),
+        None => Err(ApiError::NotFound(id)),
+    }
+}
+
+pub async fn result(
+    State(store): State<WorkStore>,
+    State(filer): State<Arc<dyn Storage + Send + Sync>>,
+    Path(id): Path<String>,
+    Query(params): Query<ResultParams>,
+) -> Result<Response, ApiError> {
+    tracing::debug!(id, params = ?params, "get result");
+    let format: ResultFormat = params
+        .format
+        .as_deref()
+        .unwrap_or("txt")
+        .parse()
+        .map_err(|err: anyhow::Error| ApiError::BadRequest(err.to_string(), id.clone()))?;
+    let job = store
+        .get(&id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(id.clone()))?;
+    if job.status != WorkStatus::Done.as_str() {
+        return Err(ApiError::NotFound(format!("{id}: job is {}", job.status)));
+    }
+    let (sub_dir, file) = split_path(result_file(&job));
+    let f_name = make_name(&file, format.extension());
+    let stream = filer
+        .read_stream(&f_name, &sub_folder(DIR_PROCESSED, &sub_dir))
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("{id}: no {f_name}")))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", f_name.replace('"', "")),
+            ),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response())
+}
+
+/// Jobs finished before the result file was tracked keep their outputs under the audio name
+fn result_file(job: &WorkData) -> &str {
+    if job.result_file.is_empty() {
+        &job.file_name
+    } else {
+        &job.result_file
+    }
+}
+
+pub async fn list(
+    State(store): State<WorkStore>,
+    Query(params): Query<ListParams>,
+) -> Result<extract::Json<JobList>, ApiError> {
+    tracing::debug!(params = ?params, "list jobs");
+    let filter =
+        to_filter(params).map_err(|err| ApiError::BadRequest(err.to_string(), "".to_string()))?;
+    let (offset, limit) = (filter.offset, filter.limit);
+    let (items, total) = store.list(filter).await?;
+    Ok(Json(JobList {
+        items: items.into_iter().map(Job::from).collect(),
+        total,
+        offset,
+        limit,
+    }))
+}
+
+fn to_filter(params: ListParams) -> anyhow::Result<WorkFilter> {
+    let status = params
+        .status
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse::<WorkStatus>())
+        .transpose()?;
+    let offset = params.offset.unwrap_or(0);
+    if offset < 0 {
+        return Err(anyhow::anyhow!("wrong offset"));
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(anyhow::anyhow!("wrong limit, expected 1..{}", MAX_LIMIT));
+    }
+    if let (Some(from), Some(to)) = (params.from, params.to) {
+        if from > to {
+            return Err(anyhow::anyhow!("wrong date range"));
+        }
+    }
+    Ok(WorkFilter {
+        status,
+        office: params.office.filter(|v| !v.is_empty()),
+        file_name: params.file.filter(|v| !v.is_empty()),
+        from: params.from.map(|v| v.and_time(chrono::NaiveTime::MIN)),
+        to: params
+            .to
+            .and_then(|v| v.succ_opt())
+            .map(|v| v.and_time(chrono::NaiveTime::MIN)),
+        offset,
+        limit,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> Option<NaiveDate> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_to_filter_default() {
+        let actual = to_filter(ListParams::default()).unwrap();
+        assert_eq!(0, actual.offset);
+        assert_eq!(DEFAULT_LIMIT, actual.limit);
+        assert!(actual.status.is_none());
+    }
+
+    #[test]
+    fn test_to_filter() {
+        let actual = to_filter(ListParams {
+            status: Some("done".to_string()),
+            office: Some("Vilnius".to_string()),
+            file: Some("".to_string()),
+            from: date("2024-08-01"),
+            to: date("2024-08-31"),
+            offset: Some(10),
+            limit: Some(20),
+        })
+        .unwrap();
+        assert_eq!(Some(WorkStatus::Done), actual.status);
+        assert_eq!(Some("Vilnius".to_string()), actual.office);
+        assert_eq!(None, actual.file_name);
+        assert_eq!(
+            "2024-08-01T00:00:00".parse::<NaiveDateTime>().ok(),
+            actual.from
+        );
+        assert_eq!(
+            "2024-09-01T00:00:00".parse::<NaiveDateTime>().ok(),
+            actual.to
+        );
+        assert_eq!(10, actual.offset);
+        assert_eq!(20, actual.limit);
+    }
+
+    #[test]
+    fn test_to_filter_fail() {
+        let wrong = [
+            ListParams {
+                status: Some("olia".to_string()),
+                ..Default::default()
+            },
+            ListParams {
+                limit: Some(0),
+                ..Default::default()
+            },
+            ListParams {
+                limit: Some(MAX_LIMIT + 1),
+                ..Default::default()
+            },
+            ListParams {
+                offset: Some(-1),
+                ..Default::default()
+            },
+            ListParams {
+                from: date("2024-08-02"),
+                to: date("2024-08-01"),
+                ..Default::default()
+            },
+        ];
+        for params in wrong {
+            assert!(to_filter(params).is_err());
+        }
+    }
+}
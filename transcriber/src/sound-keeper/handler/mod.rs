@@ -1,3 +1,4 @@
 pub mod error;
+pub mod jobs;
 pub mod live;
 pub mod upload;
@@ -1,17 +1,32 @@
-use std::{collections::hash_map, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use anyhow::anyhow;
 use axum::{
     body::Bytes,
-    extract::{self, Multipart, State},
+    extract::{self, FromRef, Multipart, State},
     BoxError, Json,
 };
 use chrono::Local;
+use futures::TryStreamExt;
 use scopeguard::guard;
 use serde::Serialize;
 use transcriber::{
-    filer::file::{make_name, Filer},
-    DIR_INCOMING, INFO_EXTENSION,
+    audio::{
+        format::{Formats, HEAD_SIZE},
+        probe::{self, AudioInfo},
+    },
+    data::{api::ASRMessage, form::FormSchema, meta},
+    filer::{
+        adder,
+        file::{make_name, sanitize_name},
+    },
+    model::models::WorkStatus,
+    postgres::work::WorkStore,
+    QSender, StatusTracker, Storage, DIR_INCOMING, DIR_WORKING, INFO_EXTENSION,
 };
 
 use super::error::ApiError;
@@ -20,23 +35,66 @@ use futures::Stream;
 
 #[derive(Serialize, Clone)]
 pub struct UploadResult {
+    /// Saved file name
     id: String,
+    file: String,
+    /// Job id if the file is sent to the queue
+    #[serde(skip_serializing_if = "Option::is_none")]
+    job_id: Option<String>,
+}
+
+/// Sends the uploaded files to the ASR queue right away
+#[derive(Clone)]
+pub struct Enqueuer {
+    pub sender: Arc<dyn QSender<ASRMessage> + Send + Sync>,
+    pub store: WorkStore,
+    /// Base dir as seen by the worker
+    pub base_dir: String,
+}
+
+#[derive(Clone)]
+pub struct UploadState {
+    pub filer: Arc<dyn Storage + Send + Sync>,
+    pub formats: Formats,
+    pub enqueuer: Option<Enqueuer>,
+    pub schema: Arc<FormSchema>,
+}
+
+impl FromRef<UploadState> for Arc<FormSchema> {
+    fn from_ref(state: &UploadState) -> Self {
+        state.schema.clone()
+    }
+}
+
+/// Upload form fields for the GUI
+pub async fn schema(State(schema): State<Arc<FormSchema>>) -> Json<FormSchema> {
+    Json(schema.as_ref().clone())
 }
 
 pub async fn handler(
-    State(filer): State<Filer>,
+    State(state): State<UploadState>,
     mut multipart: Multipart,
 ) -> Result<extract::Json<UploadResult>, ApiError> {
-    let mut values: hash_map::HashMap<String, String> = hash_map::HashMap::new();
+    let filer = state.filer.as_ref();
+    let mut values: HashMap<String, String> = HashMap::new();
     let mut saved_file: Option<String> = None;
-    let saved_file1: Option<String> = None;
-
-    let mut file_guard = guard(saved_file1, |saved_file| {
-        tracing::debug!(value = saved_file, "guard run");
-        if let Some(file) = saved_file {
-            if let Err(err) = filer.delete(&file, DIR_INCOMING) {
-                log::error!("{}", err);
-            }
+    let mut audio: Option<AudioInfo> = None;
+    let saved_file1: Option<(String, &str)> = None;
+
+    let cleaner = state.filer.clone();
+    let mut file_guard = guard(saved_file1, move |saved_file| {
+        tracing::debug!(value = ?saved_file, "guard run");
+        if let Some((file, folder)) = saved_file {
+            // the guard may run on the dropped request, so the cleanup goes on its own
+            tokio::spawn(async move {
+                if let Err(err) = cleaner.delete(&file, folder).await {
+                    log::error!("{}", err);
+                }
+                let meta = make_name(&file, INFO_EXTENSION);
+                if let Err(err) = cleaner.delete(&meta, folder).await {
+                    log::debug!("no info file: {}", err);
+                }
+            });
         }
     });
 
@@ -52,10 +110,17 @@ pub async fn handler(
             .to_string();
         let file_name = field.file_name().unwrap_or_default().to_string();
         if !file_name.is_empty() {
-            validate_name(&file_name).map_err(err_bad_request)?;
-            let saved = stream_to_file(&filer, &file_name, field).await?;
+            let file_name = validate_name(&state.formats, &file_name).map_err(err_bad_request)?;
+            let saved = stream_to_file(filer, &file_name, field).await?;
             saved_file = Some(saved.clone());
-            file_guard.replace(saved);
+            file_guard.replace((saved.clone(), DIR_INCOMING));
+            let head = filer.read_head(&saved, DIR_INCOMING, HEAD_SIZE).await?;
+            state
+                .formats
+                .check(Path::new(&saved), &head)
+                .map_err(err_bad_request)?;
+            let local = filer.local_file(&saved, DIR_INCOMING).await?;
+            audio = probe(local.path().to_path_buf()).await?;
         } else {
             let value = field
                 .text()
@@ -66,9 +131,9 @@ pub async fn handler(
         }
     }
 
-    return match saved_file {
+    match saved_file {
         Some(file) => {
-            validate(&values).map_err(err_bad_request)?;
+            let mut values = validate(&state.schema, &values).map_err(err_bad_request)?;
 
             values.insert("file".to_string(), file.to_string());
 
@@ -76,20 +141,83 @@ pub async fn handler(
             let formatted = now.format("%Y-%m-%d %H:%M:%S").to_string();
             values.insert("time".to_string(), formatted);
 
-            let data = make_data(&values)?;
-            filer.save_txt(&make_name(&file, INFO_EXTENSION), DIR_INCOMING, &data)?;
-
-            let res = UploadResult { id: file };
+            let data = make_data(values)?;
+            let res = match &state.enqueuer {
+                Some(enqueuer) => {
+                    let new_f_name = adder::move_to_working(filer, &file, "").await?;
+                    file_guard.replace((new_f_name.clone(), DIR_WORKING));
+                    filer
+                        .save_txt(&make_name(&new_f_name, INFO_EXTENSION), DIR_WORKING, &data)
+                        .await?;
+                    let job_id = enqueue(enqueuer, filer, &new_f_name, audio).await?;
+                    UploadResult {
+                        id: new_f_name.clone(),
+                        file: new_f_name,
+                        job_id: Some(job_id),
+                    }
+                }
+                None => {
+                    filer
+                        .save_txt(&make_name(&file, INFO_EXTENSION), DIR_INCOMING, &data)
+                        .await?;
+                    UploadResult {
+                        id: file.clone(),
+                        file,
+                        job_id: None,
+                    }
+                }
+            };
             file_guard.take();
             Ok(Json(res))
         }
-        None => {
-            return Err(ApiError::BadRequest(
-                "no file".to_string(),
-                "no file".to_string(),
-            ));
+        None => Err(ApiError::BadRequest(
+            "no file".to_string(),
+            "no file".to_string(),
+        )),
+    }
+}
+
+/// Registers the job and sends it to the queue, returns the job id.
+/// The job is saved first, so the sent job is always listed
+async fn enqueue(
+    enqueuer: &Enqueuer,
+    filer: &(dyn Storage + Send + Sync),
+    file: &str,
+    audio: Option<AudioInfo>,
+) -> anyhow::Result<String> {
+    let msg = adder::make_message(
+        filer,
+        file,
+        "",
+        &enqueuer.base_dir,
+        adder::folder_model(filer, "").await.as_deref(),
+        audio,
+    )
+    .await;
+    let id = msg.id.clone();
+    enqueuer.store.insert(&msg).await?;
+    if let Err(err) = enqueuer.sender.send(msg).await {
+        if let Err(err) = enqueuer
+            .store
+            .set_status(&id, WorkStatus::Failed, Some(&err.to_string()))
+            .await
+        {
+            log::error!("can't set status {}: {}", id, err);
         }
-    };
+        return Err(err);
+    }
+    tracing::info!(id, file, "sent to queue");
+    Ok(id)
+}
+
+/// Rejects the empty or corrupt audio
+async fn probe(path: PathBuf) -> Result<Option<AudioInfo>, ApiError> {
+    let res = tokio::task::spawn_blocking(move || probe::probe_file(&path))
+        .await
+        .map_err(|err| ApiError::Server(err.to_string()))?
+        .map_err(err_bad_request)?;
+    tracing::info!(audio = ?res, "probed");
+    Ok(res)
 }
 
 fn as_bad_request(msg: &str, err: anyhow::Error) -> ApiError {
@@ -100,62 +228,120 @@ fn err_bad_request(err: anyhow::Error) -> ApiError {
     ApiError::BadRequest(err.to_string(), "".to_string())
 }
 
-fn make_data(values: &hash_map::HashMap<String, String>) -> Result<String, anyhow::Error> {
-    let mut data = String::new();
-    data.push_str(&format!(
-        "File     : {}\n",
-        values.get("file").unwrap_or(&"".to_string())
-    ));
-    data.push_str(&format!(
-        "Time     : {}\n",
-        values.get("time").unwrap_or(&"".to_string())
-    ));
-    data.push_str(&format!(
-        "Name     : {}\n",
-        values.get("name").unwrap_or(&"".to_string())
-    ));
-    data.push_str(&format!(
-        "Office   : {}\n",
-        values.get("office").unwrap_or(&"".to_string())
-    ));
-    data.push_str(&format!(
-        "Speakers : {}\n",
-        values.get("speakers").unwrap_or(&"".to_string())
-    ));
-    Ok(data)
+/// The `.meta` JSON with the form values
+fn make_data(values: BTreeMap<String, String>) -> Result<String, anyhow::Error> {
+    meta::Meta::new(values).to_json()
 }
 
-fn validate(values: &hash_map::HashMap<String, String>) -> Result<(), anyhow::Error> {
-    if !values.contains_key("name") || values.get("name").is_some_and(|v| v.is_empty()) {
-        return Err(anyhow::Error::msg("no name"));
-    }
-    if !values.contains_key("office") || values.get("office").is_some_and(|v| v.is_empty()) {
-        return Err(anyhow::Error::msg("no office"));
+/// Checks the values by the schema, returns the values to keep
+fn validate(
+    schema: &FormSchema,
+    values: &HashMap<String, String>,
+) -> Result<BTreeMap<String, String>, anyhow::Error> {
+    let res = schema.validate(values)?;
+    // the worker passes these to the ASR
+    let kept: HashMap<String, String> = res.clone().into_iter().collect();
+    if kept.contains_key(meta::KEY_SPEAKERS) && meta::speakers(&kept).is_none() {
+        return Err(anyhow::Error::msg("wrong speakers"));
     }
-    if !values.contains_key("speakers") || values.get("speakers").is_some_and(|v| v.is_empty()) {
-        return Err(anyhow::Error::msg("no speakers"));
+    if let Some(model) = meta::model(&kept) {
+        meta::validate_model(&model)?;
     }
-    Ok(())
+    Ok(res)
 }
 
-async fn stream_to_file<S, E>(f: &Filer, path: &str, stream: S) -> Result<String, ApiError>
+async fn stream_to_file<S, E>(
+    f: &(dyn Storage + Send + Sync),
+    path: &str,
+    stream: S,
+) -> Result<String, ApiError>
 where
-    S: Stream<Item = Result<Bytes, E>>,
+    S: Stream<Item = Result<Bytes, E>> + Send,
     E: Into<BoxError>,
 {
-    let name = f.non_existing_name(path, DIR_INCOMING)?;
-    f.save_stream(&name, DIR_INCOMING, stream).await?;
+    let name = f.non_existing_name(path, DIR_INCOMING).await?;
+    f.save_stream(
+        &name,
+        DIR_INCOMING,
+        Box::pin(stream.map_err(std::io::Error::other)),
+    )
+    .await?;
     Ok(name)
 }
 
-fn validate_name(f_name: &str) -> Result<(), anyhow::Error> {
-    let path = Path::new(f_name);
-    let ext: String = match path.extension() {
-        Some(e) => e.to_string_lossy().to_lowercase(),
-        None => return Err(anyhow::anyhow!("audio expected")),
-    };
-    if ext != "mp3" && ext != "wav" && ext != "m4a" {
-        return Err(anyhow::anyhow!("audio expected"));
+/// Returns the sanitized client file name if it is a supported audio file
+fn validate_name(formats: &Formats, f_name: &str) -> Result<String, anyhow::Error> {
+    let res = sanitize_name(f_name)?;
+    if !formats.is_audio(Path::new(&res)) {
+        return Err(anyhow::anyhow!(
+            "audio expected, supported: {}",
+            formats.names().join(", ")
+        ));
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn values(items: &[(&str, &str)]) -> HashMap<String, String> {
+        items
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_validate() {
+        let actual = validate(
+            &FormSchema::default(),
+            &values(&[
+                ("name", "Olia"),
+                ("office", "Vilnius"),
+                ("speakers", "2"),
+                ("model", "ben"),
+                ("file", "x.wav"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(4, actual.len());
+        assert_eq!(None, actual.get("file"));
+    }
+
+    #[test_case(&[("name", "Olia"), ("office", "Vilnius")], "no speakers"; "no speakers")]
+    #[test_case(&[("name", "Olia"), ("office", "Vilnius"), ("speakers", "0")], "wrong speakers"; "zero speakers")]
+    #[test_case(&[("name", "Olia"), ("office", "Vilnius"), ("speakers", "1"), ("model", "../x")], "wrong model '../x'"; "model")]
+    fn test_validate_fail(items: &[(&str, &str)], expected: &str) {
+        let err = validate(&FormSchema::default(), &values(items)).unwrap_err();
+        assert_eq!(expected, err.to_string());
+    }
+
+    #[test_case("posėdis.wav", "posėdis.wav"; "plain")]
+    #[test_case("pose\u{307}dis.mp3", "pos\u{117}dis.mp3"; "nfc")]
+    fn test_validate_name(f_name: &str, expected: &str) {
+        assert_eq!(
+            expected,
+            validate_name(&Formats::default(), f_name).unwrap()
+        );
+    }
+
+    #[test_case("../../../etc/cron.d/job.wav"; "traversal")]
+    #[test_case("..\\..\\a.wav"; "windows traversal")]
+    #[test_case("/var/lib/a.wav"; "absolute")]
+    #[test_case("a\u{0}.wav"; "nul char")]
+    #[test_case("a.wav\r\n"; "new line")]
+    #[test_case("aux.wav"; "reserved")]
+    #[test_case(".hidden.wav"; "hidden")]
+    #[test_case("a.txt"; "not audio")]
+    fn test_validate_name_fail(f_name: &str) {
+        assert!(validate_name(&Formats::default(), f_name).is_err());
+    }
+
+    #[test]
+    fn test_make_data() {
+        let data = make_data(BTreeMap::from([("name".to_string(), "Olia".to_string())])).unwrap();
+        assert_eq!(Some(&"Olia".to_string()), meta::parse(&data).get("name"));
     }
-    Ok(())
 }
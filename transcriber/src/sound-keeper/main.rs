@@ -1,32 +1,77 @@
 pub mod handler;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::TcpListener;
 
 use clap::Parser;
-use tower_http::cors::{Any, CorsLayer};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 use tower_http::{limit::RequestBodyLimitLayer, timeout::TimeoutLayer};
 
+use deadpool_diesel::postgres::{Manager, Pool};
+use deadpool_diesel::Runtime;
+
 use axum::{
     extract::DefaultBodyLimit,
+    http::{HeaderValue, Method},
     routing::{get, post},
     Router,
 };
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use transcriber::filer::file::Filer;
-use transcriber::shutdown_signal;
+use transcriber::audio::format::{Formats, DEFAULT_FORMATS};
+use transcriber::data::form::FormSchema;
+use transcriber::filer::storage;
+use transcriber::postgres::queue::PQueue;
+use transcriber::postgres::work::WorkStore;
+use transcriber::{shutdown_signal, INPUT_QUEUE};
+
+use handler::jobs::JobsState;
+use handler::upload::{Enqueuer, UploadState};
 
 /// Sound saver http service
 #[derive(Parser, Debug)]
 #[command(version = env!("CARGO_APP_VERSION"), name = "sound-keeper", about, long_about = None)]
 struct Args {
-    /// Base working dir
+    /// Base working dir, or `s3://bucket/prefix` for the S3 compatible storage configured by
+    /// the `AWS_*` env variables
     #[arg(short, long, env)]
     base_dir: String,
 
     /// Server port
     #[arg(long, env, default_value = "8000")]
     port: i32,
+
+    /// Postgres SQL connection string, for the queue and the jobs API
+    #[arg(short, long, env)]
+    postgres_url: Option<String>,
+
+    /// Serve the job status and result API. It has no auth, keep it on the internal network
+    #[arg(long, env, default_value = "false", requires = "postgres_url")]
+    jobs_api: bool,
+
+    /// Browser origins allowed to call the service, comma separated. Any origin may upload
+    /// if not set, the jobs API is served to the listed origins only
+    #[arg(long, env, value_delimiter = ',')]
+    cors_origins: Vec<String>,
+
+    /// Send uploaded files to the ASR queue instead of leaving them for file-adder
+    #[arg(long, env, default_value = "false", requires = "postgres_url")]
+    enqueue: bool,
+
+    /// Accepted audio formats, comma separated
+    #[arg(long, env = "AUDIO_FORMATS", default_value = DEFAULT_FORMATS)]
+    formats: String,
+
+    /// Server base working dir, as seen by the worker
+    #[arg(short, long, env, default_value = "")]
+    server_base_dir: String,
+
+    /// Upload form fields JSON file, the built-in fields are used if not set
+    #[arg(long, env)]
+    schema_file: Option<String>,
+
+    #[command(flatten)]
+    dirs: storage::Dirs,
 }
 
 async fn main_int(args: Args) -> anyhow::Result<()> {
@@ -36,24 +81,86 @@ async fn main_int(args: Args) -> anyhow::Result<()> {
     tracing::info!(port = args.port, "port");
     log::info!("Init tracing...");
 
-    log::info!("Connecting to postgres...");
-    let f = Filer::new(&args.base_dir);
+    let f = storage::open(&args.base_dir, &args.dirs)?;
+    let formats = Formats::new(&args.formats)?;
+    tracing::info!(formats = ?formats.names(), "audio");
+    let schema = match &args.schema_file {
+        Some(path) => FormSchema::load(path)?,
+        None => FormSchema::default(),
+    };
+    tracing::info!(
+        file = args.schema_file,
+        fields = schema.fields.len(),
+        "schema"
+    );
+
+    let origins = args
+        .cors_origins
+        .iter()
+        .map(|origin| origin.trim().parse::<HeaderValue>())
+        .collect::<Result<Vec<_>, _>>()?;
+    tracing::info!(origins = ?args.cors_origins, "cors");
 
-    let cors = CorsLayer::new()
-        .allow_methods([axum::http::Method::GET, axum::http::Method::POST])
-        .allow_origin(Any);
+    let store = match &args.postgres_url {
+        Some(url) => {
+            log::info!("Connecting to postgres...");
+            let manager = Manager::new(url.clone(), Runtime::Tokio1);
+            let pool = Pool::builder(manager).max_size(4).build()?;
+            Some(WorkStore::new(pool))
+        }
+        None => None,
+    };
 
-    let app = Router::new()
+    let mut enqueuer = None;
+    if let (true, Some(url), Some(store)) = (args.enqueue, &args.postgres_url, &store) {
+        tracing::info!("uploads go to the queue");
+        let pq = PQueue::new(url, INPUT_QUEUE)
+            .await
+            .map_err(anyhow::Error::msg)?;
+        let mut s_dir = args.server_base_dir.as_str();
+        if s_dir.is_empty() {
+            s_dir = &args.base_dir;
+        }
+        enqueuer = Some(Enqueuer {
+            sender: Arc::new(pq),
+            store: store.clone(),
+            base_dir: s_dir.to_string(),
+        });
+    }
+
+    let mut app = Router::new()
         .route("/live", get(handler::live::handler))
         .route("/upload", post(handler::upload::handler))
+        .route("/schema", get(handler::upload::schema))
         .layer(DefaultBodyLimit::disable())
         .layer(RequestBodyLimitLayer::new(500 * 1024 * 1024))
-        .with_state(f)
-        .layer((
-            TraceLayer::new_for_http(),
-            TimeoutLayer::new(Duration::from_secs(40)),
-            cors,
-        ));
+        .layer(make_cors(&origins, true))
+        .with_state(UploadState {
+            filer: f.clone(),
+            formats,
+            enqueuer,
+            schema: Arc::new(schema),
+        });
+
+    match store {
+        Some(store) if args.jobs_api => {
+            let state = JobsState { store, filer: f };
+            app = app.merge(
+                Router::new()
+                    .route("/jobs", get(handler::jobs::list))
+                    .route("/jobs/:id", get(handler::jobs::get))
+                    .route("/jobs/:id/result", get(handler::jobs::result))
+                    .layer(make_cors(&origins, false))
+                    .with_state(state),
+            );
+        }
+        _ => tracing::info!("jobs API disabled"),
+    }
+
+    let app = app.layer((
+        TraceLayer::new_for_http(),
+        TimeoutLayer::new(Duration::from_secs(40)),
+    ));
 
     let listener = TcpListener::bind(format!("0.0.0.0:{}", args.port)).await?;
 
@@ -65,6 +172,15 @@ async fn main_int(args: Args) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Allows the listed origins, or any origin if none are listed and `any` is set
+fn make_cors(origins: &[HeaderValue], any: bool) -> CorsLayer {
+    let cors = CorsLayer::new().allow_methods([Method::GET, Method::POST]);
+    if origins.is_empty() && any {
+        return cors.allow_origin(Any);
+    }
+    cors.allow_origin(AllowOrigin::list(origins.iter().cloned()))
+}
+
 #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::registry()
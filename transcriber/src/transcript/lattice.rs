@@ -0,0 +1,210 @@
+use super::{Segment, Transcript, Word};
+
+const SILENCE: &str = "<eps>";
+
+/// Parses the restored lattice (`lat.restored.txt`).
+///
+/// A segment starts with the header `# <num> <speaker>`, followed by the word lines
+/// `<main> <start> <end> <word> [<punctuation>]`, times in seconds.
+/// Only the best path words (`main` = 1) are taken, silence and empty lines are skipped
+pub fn parse(txt: &str) -> anyhow::Result<Transcript> {
+    let mut res = Transcript::default();
+    let mut current: Option<Segment> = None;
+    for (i, line) in txt.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields[0] == "#" {
+            if fields.len() < 3 {
+                return Err(anyhow::anyhow!("line {}: wrong header '{}'", i + 1, line));
+            }
+            push(&mut res, current.take());
+            current = Some(Segment {
+                speaker: fields[2].to_string(),
+                ..Default::default()
+            });
+            continue;
+        }
+        if fields.len() < 4 {
+            return Err(anyhow::anyhow!("line {}: wrong word '{}'", i + 1, line));
+        }
+        let segment = current
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("line {}: word before the header", i + 1))?;
+        if fields[0] != "1" || fields[3] == SILENCE {
+            continue;
+        }
+        let start = parse_time(fields[1], i)?;
+        let end = parse_time(fields[2], i)?;
+        if end < start {
+            return Err(anyhow::anyhow!("line {}: end before start", i + 1));
+        }
+        let mut text = fields[3].to_string();
+        if let Some(punct) = fields.get(4) {
+            text.push_str(punct);
+        }
+        segment.words.push(Word { start, end, text });
+    }
+    push(&mut res, current);
+    Ok(res)
+}
+
+/// Joins the lattices of the consecutive audio parts, `(offset, lattice)`.
+/// The word times are moved by the part offset and the segments renumbered,
+/// speaker labels are left as the recognizer gave them for each part
+pub fn merge(parts: &[(f64, &str)]) -> anyhow::Result<String> {
+    let mut res = String::new();
+    let mut num = 0;
+    for (offset, txt) in parts {
+        for (i, line) in txt.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() {
+                res.push('\n');
+                continue;
+            }
+            if fields[0] == "#" {
+                if fields.len() < 3 {
+                    return Err(anyhow::anyhow!("line {}: wrong header '{}'", i + 1, line));
+                }
+                num += 1;
+                res.push_str(&format!("# {} {}\n", num, fields[2..].join(" ")));
+                continue;
+            }
+            if fields.len() < 4 {
+                return Err(anyhow::anyhow!("line {}: wrong word '{}'", i + 1, line));
+            }
+            let start = parse_time(fields[1], i)? + offset;
+            let end = parse_time(fields[2], i)? + offset;
+            res.push_str(&format!(
+                "{} {:.2} {:.2} {}\n",
+                fields[0],
+                start,
+                end,
+                fields[3..].join(" ")
+            ));
+        }
+        if !res.is_empty() && !res.ends_with("\n\n") {
+            res.push('\n');
+        }
+    }
+    Ok(res)
+}
+
+fn parse_time(v: &str, line: usize) -> anyhow::Result<f64> {
+    v.parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite() && *v >= 0.0)
+        .ok_or_else(|| anyhow::anyhow!("line {}: wrong time '{}'", line + 1, v))
+}
+
+fn push(res: &mut Transcript, segment: Option<Segment>) {
+    if let Some(mut segment) = segment {
+        if let (Some(first), Some(last)) = (segment.words.first(), segment.words.last()) {
+            segment.start = first.start;
+            segment.end = last.end;
+            res.segments.push(segment);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    const SAMPLE: &str = "# 1 S0000
+1 0.00 0.21 <eps>
+1 0.21 0.60 Labas
+1 0.60 1.02 rytas ,
+1 1.02 1.50 Lietuva .
+
+# 2 S0001
+1 1.80 2.10 Kaip
+0 1.80 2.10 kai
+1 2.10 2.40 sekasi ?
+";
+
+    #[test]
+    fn test_parse() {
+        let actual = parse(SAMPLE).unwrap();
+        assert_eq!(2, actual.segments.len());
+        let first = &actual.segments[0];
+        assert_eq!("S0000", first.speaker);
+        assert_eq!(0.21, first.start);
+        assert_eq!(1.5, first.end);
+        assert_eq!("Labas rytas, Lietuva.", first.text());
+        let second = &actual.segments[1];
+        assert_eq!("S0001", second.speaker);
+        assert_eq!(
+            vec![
+                Word {
+                    start: 1.8,
+                    end: 2.1,
+                    text: "Kaip".to_string()
+                },
+                Word {
+                    start: 2.1,
+                    end: 2.4,
+                    text: "sekasi?".to_string()
+                }
+            ],
+            second.words
+        );
+    }
+
+    #[test]
+    fn test_parse_silence_only() {
+        let actual = parse("# 1 S0000\n1 0.00 2.00 <eps>\n# 2 S0000\n1 2.00 2.50 Ačiū\n").unwrap();
+        assert_eq!(1, actual.segments.len());
+        assert_eq!("Ačiū", actual.segments[0].text());
+    }
+
+    #[test_case(""; "empty")]
+    #[test_case("\n\n"; "empty lines")]
+    fn test_parse_empty(txt: &str) {
+        assert_eq!(Transcript::default(), parse(txt).unwrap());
+    }
+
+    #[test_case("# 1\n1 0 1 a"; "header")]
+    #[test_case("1 0 1 a"; "no header")]
+    #[test_case("# 1 S0\n1 0 1"; "short word")]
+    #[test_case("# 1 S0\n1 x 1 a"; "time")]
+    #[test_case("# 1 S0\n1 -1 1 a"; "negative")]
+    #[test_case("# 1 S0\n1 2 1 a"; "end before start")]
+    fn test_parse_fail(txt: &str) {
+        assert!(parse(txt).is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let actual = merge(&[
+            (0.0, "# 1 S0000\n1 0.00 0.50 Labas .\n"),
+            (
+                600.0,
+                "# 1 S0000\n1 0.10 0.50 Kaip\n1 0.50 1.00 sekasi ?\n\n# 2 S0001\n1 1.20 1.50 Gerai\n",
+            ),
+        ])
+        .unwrap();
+        assert_eq!(
+            "# 1 S0000\n1 0.00 0.50 Labas .\n\n# 2 S0000\n1 600.10 600.50 Kaip\n\
+             1 600.50 601.00 sekasi ?\n\n# 3 S0001\n1 601.20 601.50 Gerai\n\n",
+            actual
+        );
+        let transcript = parse(&actual).unwrap();
+        assert_eq!(3, transcript.segments.len());
+        assert_eq!(600.1, transcript.segments[1].start);
+    }
+
+    #[test_case(&[]; "none")]
+    #[test_case(&[(10.0, "")]; "empty part")]
+    fn test_merge_empty(parts: &[(f64, &str)]) {
+        assert_eq!("", merge(parts).unwrap());
+    }
+
+    #[test]
+    fn test_merge_fail() {
+        assert!(merge(&[(0.0, "# 1 S0\n1 x 1 a\n")]).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+pub mod lattice;
+pub mod subtitles;
+
+use serde::Serialize;
+
+use crate::data::meta::Meta;
+
+/// Recognized text with the timings, built from the ASR lattice
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Transcript {
+    pub segments: Vec<Segment>,
+}
+
+/// Continuous speech of one speaker
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Segment {
+    pub speaker: String,
+    /// Seconds from the audio start
+    pub start: f64,
+    pub end: f64,
+    pub words: Vec<Word>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Word {
+    pub start: f64,
+    pub end: f64,
+    /// Word with the restored punctuation
+    pub text: String,
+}
+
+impl Segment {
+    pub fn text(&self) -> String {
+        join(&self.words)
+    }
+}
+
+pub(crate) fn join(words: &[Word]) -> String {
+    words
+        .iter()
+        .map(|w| w.text.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Writes the transcript as the JSON document with the segment texts and the job metadata
+pub fn to_json(transcript: &Transcript, meta: Option<&Meta>) -> anyhow::Result<String> {
+    #[derive(Serialize)]
+    struct JsonSegment<'a> {
+        speaker: &'a str,
+        start: f64,
+        end: f64,
+        text: String,
+        words: &'a [Word],
+    }
+    #[derive(Serialize)]
+    struct JsonTranscript<'a> {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        meta: Option<&'a Meta>,
+        segments: Vec<JsonSegment<'a>>,
+    }
+    let res = JsonTranscript {
+        meta,
+        segments: transcript
+            .segments
+            .iter()
+            .map(|s| JsonSegment {
+                speaker: &s.speaker,
+                start: s.start,
+                end: s.end,
+                text: s.text(),
+                words: &s.words,
+            })
+            .collect(),
+    };
+    Ok(serde_json::to_string_pretty(&res)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let transcript =
+            lattice::parse("# 1 S0001\n1 0.00 0.50 Labas\n1 0.50 1.00 rytas .\n").unwrap();
+        let actual: serde_json::Value =
+            serde_json::from_str(&to_json(&transcript, None).unwrap()).unwrap();
+        assert_eq!(
+            serde_json::json!({"segments": [{
+                "speaker": "S0001",
+                "start": 0.0,
+                "end": 1.0,
+                "text": "Labas rytas.",
+                "words": [
+                    {"start": 0.0, "end": 0.5, "text": "Labas"},
+                    {"start": 0.5, "end": 1.0, "text": "rytas."}
+                ]
+            }]}),
+            actual
+        );
+    }
+
+    #[test]
+    fn test_to_json_meta() {
+        let transcript = lattice::parse("# 1 S0001\n1 0.00 0.50 Labas\n").unwrap();
+        let meta = Meta::new([("name".to_string(), "Olia".to_string())]);
+        let actual: serde_json::Value =
+            serde_json::from_str(&to_json(&transcript, Some(&meta)).unwrap()).unwrap();
+        assert_eq!(
+            serde_json::json!({"version": 1, "values": {"name": "Olia"}}),
+            actual["meta"]
+        );
+        assert_eq!(1, actual["segments"].as_array().unwrap().len());
+    }
+}
@@ -0,0 +1,193 @@
+use std::fmt::Write;
+
+use super::{join, Transcript};
+
+const MAX_CUE_CHARS: usize = 80;
+const MAX_CUE_SECS: f64 = 7.0;
+
+/// One subtitle shown on the screen
+#[derive(Debug, Clone, PartialEq)]
+struct Cue {
+    start: f64,
+    end: f64,
+    speaker: String,
+    text: String,
+}
+
+/// Splits the segments into cues short enough to read
+fn cues(transcript: &Transcript) -> Vec<Cue> {
+    let mut res = Vec::new();
+    for segment in &transcript.segments {
+        let mut from = 0;
+        for i in 1..=segment.words.len() {
+            let next = segment.words.get(i);
+            let fits = next.is_some_and(|w| {
+                w.end - segment.words[from].start <= MAX_CUE_SECS
+                    && join(&segment.words[from..=i]).chars().count() <= MAX_CUE_CHARS
+            });
+            if !fits {
+                let words = &segment.words[from..i];
+                res.push(Cue {
+                    start: words[0].start,
+                    end: words[words.len() - 1].end,
+                    speaker: segment.speaker.clone(),
+                    text: join(words),
+                });
+                from = i;
+            }
+        }
+    }
+    res
+}
+
+pub fn to_srt(transcript: &Transcript) -> String {
+    let mut res = String::new();
+    for (i, cue) in cues(transcript).iter().enumerate() {
+        _ = writeln!(
+            res,
+            "{}\n{} --> {}\n{}\n",
+            i + 1,
+            format_time(cue.start, ','),
+            format_time(cue.end, ','),
+            cue.text
+        );
+    }
+    res
+}
+
+/// Writes WebVTT, the speaker goes into the voice tag
+pub fn to_vtt(transcript: &Transcript) -> String {
+    let mut res = String::from("WEBVTT\n\n");
+    for cue in cues(transcript) {
+        _ = writeln!(
+            res,
+            "{} --> {}\n<v {}>{}\n",
+            format_time(cue.start, '.'),
+            format_time(cue.end, '.'),
+            cue.speaker,
+            escape_vtt(&cue.text)
+        );
+    }
+    res
+}
+
+fn escape_vtt(v: &str) -> String {
+    v.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Formats seconds as `hh:mm:ss<sep>mmm`
+fn format_time(secs: f64, sep: char) -> String {
+    let ms = (secs * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        sep,
+        ms % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcript::{lattice, Segment, Word};
+    use test_case::test_case;
+
+    const SAMPLE: &str = "# 1 S0000
+1 0.21 0.60 Labas
+1 0.60 1.02 rytas .
+# 2 S0001
+1 1.80 2.10 Kaip
+1 2.10 2.40 sekasi ?
+";
+
+    #[test_case(0.0, ',', "00:00:00,000"; "zero")]
+    #[test_case(1.2345, '.', "00:00:01.235"; "round")]
+    #[test_case(3723.5, ',', "01:02:03,500"; "hours")]
+    fn test_format_time(secs: f64, sep: char, expected: &str) {
+        assert_eq!(expected, format_time(secs, sep));
+    }
+
+    #[test]
+    fn test_to_srt() {
+        let transcript = lattice::parse(SAMPLE).unwrap();
+        assert_eq!(
+            "1\n00:00:00,210 --> 00:00:01,020\nLabas rytas.\n\n\
+             2\n00:00:01,800 --> 00:00:02,400\nKaip sekasi?\n\n",
+            to_srt(&transcript)
+        );
+    }
+
+    #[test]
+    fn test_to_vtt() {
+        let transcript = lattice::parse(SAMPLE).unwrap();
+        assert_eq!(
+            "WEBVTT\n\n\
+             00:00:00.210 --> 00:00:01.020\n<v S0000>Labas rytas.\n\n\
+             00:00:01.800 --> 00:00:02.400\n<v S0001>Kaip sekasi?\n\n",
+            to_vtt(&transcript)
+        );
+    }
+
+    #[test]
+    fn test_to_vtt_empty() {
+        assert_eq!("WEBVTT\n\n", to_vtt(&Transcript::default()));
+    }
+
+    #[test]
+    fn test_cues_split() {
+        let words = (0..20)
+            .map(|i| Word {
+                start: i as f64,
+                end: i as f64 + 1.0,
+                text: "žodis".to_string(),
+            })
+            .collect::<Vec<_>>();
+        let transcript = Transcript {
+            segments: vec![Segment {
+                speaker: "S0000".to_string(),
+                start: 0.0,
+                end: 20.0,
+                words,
+            }],
+        };
+        let actual = cues(&transcript);
+        assert_eq!(3, actual.len());
+        assert_eq!(0.0, actual[0].start);
+        assert_eq!(7.0, actual[0].end);
+        assert_eq!(7.0, actual[1].start);
+        assert_eq!(20.0, actual[2].end);
+        assert!(actual
+            .iter()
+            .all(|c| c.text.chars().count() <= MAX_CUE_CHARS));
+    }
+
+    #[test]
+    fn test_cues_long_words() {
+        let transcript = Transcript {
+            segments: vec![Segment {
+                speaker: "S0000".to_string(),
+                start: 0.0,
+                end: 2.0,
+                words: vec![
+                    Word {
+                        start: 0.0,
+                        end: 1.0,
+                        text: "a".repeat(MAX_CUE_CHARS),
+                    },
+                    Word {
+                        start: 1.0,
+                        end: 2.0,
+                        text: "b".to_string(),
+                    },
+                ],
+            }],
+        };
+        let actual = cues(&transcript);
+        assert_eq!(2, actual.len());
+        assert_eq!("b", actual[1].text);
+    }
+}
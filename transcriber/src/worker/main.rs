@@ -1,14 +1,20 @@
 use deadpool_diesel::postgres::{Manager, Pool};
 use deadpool_diesel::Runtime;
 use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use transcriber::asr::client::ASRClient;
-use transcriber::asr::{clean_worker, res_worker, worker};
-use transcriber::filer::file::Filer;
+use transcriber::asr::client::{self, ASRClient};
+use transcriber::asr::{clean_worker, res_worker, status_worker, worker, AsrBackend};
+use transcriber::audio::chunk::ChunkParams;
+use transcriber::audio::normalize::NormalizeParams;
+use transcriber::audio::vad::VadParams;
+use transcriber::filer::storage;
 use transcriber::postgres::queue::PQueue;
-use transcriber::{shutdown_signal, CLEAN_QUEUE, INPUT_QUEUE, RESULT_QUEUE};
+use transcriber::postgres::work::WorkStore;
+use transcriber::{shutdown_signal, CLEAN_QUEUE, INPUT_QUEUE, RESULT_QUEUE, STATUS_QUEUE};
 
 use clap::Parser;
 
@@ -16,7 +22,8 @@ use clap::Parser;
 #[derive(Parser, Debug)]
 #[command(version = env!("CARGO_APP_VERSION"), name = "asr-worker", about, long_about = None)]
 struct Args {
-    /// Base working dir
+    /// Base working dir, or `s3://bucket/prefix` for the S3 compatible storage configured by
+    /// the `AWS_*` env variables
     #[arg(short, long, env)]
     base_dir: String,
 
@@ -43,6 +50,48 @@ struct Args {
     /// ASR recognizer
     #[arg(long, env, default_value = "false")]
     old_clean_service: bool,
+
+    /// Upload field names of the `asr_*` meta values, e.g.
+    /// `skip_punctuation=skipPunctuation,skip_diarization=skipSpeakerDiarization`.
+    /// The unmapped values go under their own name
+    #[arg(long, env, default_value = "")]
+    asr_params: String,
+
+    /// Max wait for the ASR job to finish, in minutes
+    #[arg(long, env, default_value = "60")]
+    status_timeout: u64,
+
+    /// Re-encode the audio to 16 bit PCM wav before the upload
+    #[arg(long, env, default_value = "false")]
+    normalize: bool,
+
+    /// Target sample rate for --normalize
+    #[arg(long, env, default_value = "16000")]
+    normalize_rate: u32,
+
+    /// Target channels for --normalize, 1 - mono, 0 - keep the source channels
+    #[arg(long, env, default_value = "1", value_parser = clap::value_parser!(u16).range(0..=1))]
+    normalize_channels: u16,
+
+    /// Split the audio longer than this into the chunks transcribed in parallel, in minutes,
+    /// 0 - never split
+    #[arg(long, env, default_value = "0")]
+    chunk_len: u64,
+
+    /// Look for a pause this far back from the chunk end, in seconds
+    #[arg(long, env, default_value = "60")]
+    chunk_search: u64,
+
+    /// Skip the audio with less speech than this, in seconds, 0 - send everything
+    #[arg(long, env, default_value = "0")]
+    vad_min_speech: f64,
+
+    /// Audio louder than this is taken as speech, in dBFS
+    #[arg(long, env, default_value = "-40", allow_negative_numbers = true)]
+    vad_threshold: f32,
+
+    #[command(flatten)]
+    dirs: storage::Dirs,
 }
 
 async fn main_int(args: Args) -> Result<(), Box<dyn Error + Send + Sync>> {
@@ -52,21 +101,38 @@ async fn main_int(args: Args) -> Result<(), Box<dyn Error + Send + Sync>> {
     log::info!("ASR URL      : {}", args.asr_url);
     log::info!("ASR Model    : {}", args.asr_recognizer);
     log::info!("Old clean    : {}", args.old_clean_service);
+    log::info!("Status wait  : {}m", args.status_timeout);
+    let normalize = args.normalize.then_some(NormalizeParams {
+        sample_rate: args.normalize_rate,
+        channels: args.normalize_channels,
+    });
+    log::info!("Normalize    : {:?}", normalize);
+    log::info!("Chunk        : {}m", args.chunk_len);
+    let vad = (args.vad_min_speech > 0.0).then_some(VadParams {
+        threshold: args.vad_threshold,
+        min_speech: args.vad_min_speech,
+    });
+    log::info!("VAD          : {:?}", vad);
 
-    let f = Filer::new(&args.base_dir);
+    let f = storage::open(&args.base_dir, &args.dirs)?;
     log::info!("Connecting to postgres...");
     let pq = PQueue::new(&args.postgres_url, INPUT_QUEUE).await?;
+    let pq_status = PQueue::new(&args.postgres_url, STATUS_QUEUE).await?;
     let pq_res = PQueue::new(&args.postgres_url, RESULT_QUEUE).await?;
     let pq_clean = PQueue::new(&args.postgres_url, CLEAN_QUEUE).await?;
 
     let manager = Manager::new(args.postgres_url, Runtime::Tokio1);
     let pool = Pool::builder(manager).max_size(8).build()?;
-    let asr_client = ASRClient::new(
-        &args.asr_url,
-        &args.asr_auth_key,
-        &args.asr_recognizer,
-        args.old_clean_service,
-    )?;
+    let store = WorkStore::new(pool.clone());
+    let asr_client: Arc<dyn AsrBackend + Send + Sync> = Arc::new(
+        ASRClient::new(
+            &args.asr_url,
+            &args.asr_auth_key,
+            &args.asr_recognizer,
+            args.old_clean_service,
+        )?
+        .with_param_names(client::parse_param_names(&args.asr_params)?),
+    );
     let token = CancellationToken::new();
 
     let tracker = TaskTracker::new();
@@ -78,21 +144,49 @@ async fn main_int(args: Args) -> Result<(), Box<dyn Error + Send + Sync>> {
             pool.clone(),
             asr_client.clone(),
             Box::new(pq_res.clone()),
+            Box::new(pq_status.clone()),
             pq.clone(),
+            Box::new(store.clone()),
         )
-        .await?;
+        .await?
+        .with_normalize(normalize.clone())
+        .with_dirs(args.dirs.clone())
+        .with_chunking((args.chunk_len > 0).then(|| worker::Chunking {
+            params: ChunkParams {
+                max_len: (args.chunk_len * 60) as f64,
+                search: args.chunk_search as f64,
+            },
+            queue: Box::new(pq.clone()),
+        }))
+        .with_vad(vad.clone());
         tracker.spawn(async move {
             if let Err(e) = worker.run().await {
                 log::error!("{}", e);
             }
         });
     }
+    let worker = status_worker::Worker::new(
+        token.clone(),
+        asr_client.clone(),
+        pq_status.clone(),
+        Box::new(pq_status),
+        Box::new(pq_res.clone()),
+        Duration::from_secs(args.status_timeout * 60),
+        Box::new(store.clone()),
+    )
+    .await?;
+    tracker.spawn(async move {
+        if let Err(e) = worker.run().await {
+            log::error!("{}", e);
+        }
+    });
     let worker = res_worker::Worker::new(
         token.clone(),
         asr_client.clone(),
         pq_res,
         f,
         Box::new(pq_clean.clone()),
+        Box::new(store.clone()),
     )
     .await?;
     tracker.spawn(async move {
@@ -100,7 +194,9 @@ async fn main_int(args: Args) -> Result<(), Box<dyn Error + Send + Sync>> {
             log::error!("{}", e);
         }
     });
-    let worker = clean_worker::Worker::new(token.clone(), asr_client.clone(), pq_clean).await?;
+    let worker =
+        clean_worker::Worker::new(token.clone(), asr_client.clone(), pq_clean, Box::new(store))
+            .await?;
     tracker.spawn(async move {
         if let Err(e) = worker.run().await {
             log::error!("{}", e);